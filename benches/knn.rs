@@ -0,0 +1,137 @@
+//! Compares a full `vec_distance_L2` scan over `Faces` with the `vec0` KNN lookup.
+//!
+//! Run with `cargo bench --bench knn -- [NUM_FACES]`.
+
+use sqlite_vec::sqlite3_vec_init;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{Pool, Sqlite};
+use std::ffi::{c_char, c_int};
+use std::time::{Duration, Instant};
+use zerocopy::IntoBytes;
+
+const DIMENSIONS: usize = 128;
+const QUERIES: usize = 50;
+const K: u32 = 10;
+
+/// Deterministic xorshift so runs are comparable without pulling in `rand`.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+    }
+
+    fn encoding(&mut self) -> Vec<f32> {
+        (0..DIMENSIONS).map(|_| self.next_f32()).collect()
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let num_faces: usize = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(100_000);
+
+    unsafe {
+        libsqlite3_sys::sqlite3_auto_extension(Some(std::mem::transmute::<
+            *const (),
+            unsafe extern "C" fn(
+                *mut libsqlite3_sys::sqlite3,
+                *mut *mut c_char,
+                *const libsqlite3_sys::sqlite3_api_routines,
+            ) -> c_int,
+        >(sqlite3_vec_init as *const ())));
+    }
+
+    // a single connection, otherwise every pooled connection gets its own in-memory database
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    sqlx::migrate!("./src/migrations").run(&db).await.unwrap();
+
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    populate(&db, &mut rng, num_faces).await;
+
+    let queries: Vec<Vec<f32>> = (0..QUERIES).map(|_| rng.encoding()).collect();
+
+    let scan = measure(&db, &queries, SCAN_QUERY).await;
+    let knn = measure(&db, &queries, KNN_QUERY).await;
+
+    println!("faces: {num_faces}, queries: {QUERIES}, k: {K}");
+    println!("full scan: {:?} per query", scan / QUERIES as u32);
+    println!("vec0 knn:  {:?} per query", knn / QUERIES as u32);
+}
+
+const SCAN_QUERY: &str = "
+    -- noinspection SqlResolve
+    SELECT Id, vec_distance_L2(FaceEncoding, $1) AS distance
+    FROM Faces
+    ORDER BY distance
+    LIMIT $2;
+";
+
+const KNN_QUERY: &str = "
+    -- noinspection SqlResolve
+    SELECT FaceId, distance
+    FROM FaceEncodings
+    WHERE FaceEncoding MATCH $1
+      AND k = $2
+    ORDER BY distance;
+";
+
+async fn populate(db: &Pool<Sqlite>, rng: &mut XorShift, num_faces: usize) {
+    let mut tx = db.begin().await.unwrap();
+
+    for _ in 0..num_faces {
+        let encoding = rng.encoding();
+
+        let face_id = sqlx::query(
+            "INSERT INTO Faces (FaceEncoding, RectLeft, RectTop, RectRight, RectBottom)
+             VALUES ($1, 0, 0, 1, 1)",
+        )
+        .bind(encoding.as_bytes())
+        .execute(&mut *tx)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+
+        sqlx::query(
+            "
+            -- noinspection SqlResolve
+            INSERT INTO FaceEncodings (FaceId, FaceEncoding, PersonId, Quality)
+            VALUES ($1, $2, 0, 1.0)
+            ",
+        )
+        .bind(face_id)
+        .bind(encoding.as_bytes())
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    }
+
+    tx.commit().await.unwrap();
+}
+
+async fn measure(db: &Pool<Sqlite>, queries: &[Vec<f32>], sql: &str) -> Duration {
+    let start = Instant::now();
+
+    for query in queries {
+        let rows: Vec<(i64, f32)> = sqlx::query_as(sql)
+            .bind(query.as_bytes())
+            .bind(K)
+            .fetch_all(db)
+            .await
+            .unwrap();
+
+        assert_eq!(rows.len(), K as usize);
+    }
+
+    start.elapsed()
+}
@@ -0,0 +1,79 @@
+//! Graph clustering of face encodings.
+
+/// Cluster the nodes of an undirected graph with dlib's `chinese_whispers`.
+///
+/// Nodes are numbered `0..num_nodes` and every edge has the same weight.
+/// Returns a cluster label for each node; labels are numbered from 0.
+pub fn chinese_whispers(num_nodes: usize, edges: &[(usize, usize)], iterations: usize) -> Vec<u64> {
+    if num_nodes == 0 {
+        return Vec::new();
+    }
+
+    // dlib sizes the labels by the highest node index present in the edges, so every node
+    // gets a self loop to make sure isolated nodes still get a label of their own
+    let mut flat_edges = Vec::with_capacity((edges.len() + num_nodes) * 2);
+    for node in 0..num_nodes {
+        flat_edges.push(node as u64);
+        flat_edges.push(node as u64);
+    }
+    for &(from, to) in edges {
+        assert!(from < num_nodes && to < num_nodes, "edge node out of range");
+        flat_edges.push(from as u64);
+        flat_edges.push(to as u64);
+    }
+
+    let num_edges = flat_edges.len() / 2;
+    let edges_ptr = flat_edges.as_ptr();
+    let mut labels = vec![0u64; num_nodes];
+    let labels_ptr = labels.as_mut_ptr();
+
+    unsafe {
+        cpp!([
+                edges_ptr as "const uint64_t*",
+                num_edges as "size_t",
+                iterations as "size_t",
+                labels_ptr as "uint64_t*"
+            ] {
+            std::vector<sample_pair> pairs;
+            pairs.reserve(num_edges);
+
+            for (size_t i = 0; i < num_edges; i++) {
+                pairs.push_back(sample_pair(edges_ptr[2 * i], edges_ptr[2 * i + 1]));
+            }
+
+            std::vector<unsigned long> result;
+            dlib::rand rnd;
+            chinese_whispers(pairs, result, iterations, rnd);
+
+            for (size_t i = 0; i < result.size(); i++) {
+                labels_ptr[i] = result[i];
+            }
+        })
+    }
+
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chinese_whispers() {
+        let edges = [(0, 1), (1, 2), (0, 2), (3, 4)];
+        let labels = chinese_whispers(6, &edges, 100);
+
+        assert_eq!(labels.len(), 6);
+        assert_eq!(labels[0], labels[1]);
+        assert_eq!(labels[1], labels[2]);
+        assert_eq!(labels[3], labels[4]);
+        assert_ne!(labels[0], labels[3]);
+        assert_ne!(labels[5], labels[0]);
+        assert_ne!(labels[5], labels[3]);
+    }
+
+    #[test]
+    fn test_no_nodes() {
+        assert!(chinese_whispers(0, &[], 100).is_empty());
+    }
+}
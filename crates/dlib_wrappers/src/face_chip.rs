@@ -0,0 +1,232 @@
+//! Extracting aligned images of faces.
+
+use crate::ImageMatrix;
+use crate::face_encoding::FaceChips;
+use crate::landmark_prediction::FaceLandmarks;
+use image::RgbImage;
+
+/// Cuts faces out of an image, rotated and scaled so that the eyes and mouth of every face
+/// land in the same place, like the chips the face encoding network is fed with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FaceChipExtractor {
+    size: u32,
+    padding: f64,
+}
+
+impl FaceChipExtractor {
+    /// Size of the chips used for face encodings.
+    pub const DEFAULT_SIZE: u32 = 150;
+    /// Padding of the chips used for face encodings.
+    pub const DEFAULT_PADDING: f64 = 0.25;
+    /// Largest supported size, a chip of which takes 48 MiB.
+    pub const MAX_SIZE: u32 = 4096;
+
+    /// Extracts chips of `size` x `size` pixels.
+    ///
+    /// `padding` is the margin around the face as a fraction of its size, 0 crops tightly
+    /// around the landmarks. Fails for a size of 0 or above [`Self::MAX_SIZE`] and for a
+    /// negative padding.
+    pub fn new(size: u32, padding: f64) -> Result<Self, String> {
+        if size == 0 || size > Self::MAX_SIZE {
+            return Err(format!(
+                "Face chips must be between 1 and {} pixels wide, not {}",
+                Self::MAX_SIZE,
+                size
+            ));
+        }
+        // dlib checks the padding only in debug builds
+        if !(padding >= 0.0 && padding.is_finite()) {
+            return Err(format!(
+                "Padding of face chips must be a non-negative number, not {}",
+                padding
+            ));
+        }
+
+        Ok(Self { size, padding })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn padding(&self) -> f64 {
+        self.padding
+    }
+
+    /// Extracts one chip per face, in the order of the landmarks.
+    ///
+    /// Only landmarks of the 5 and 68 point models can be aligned.
+    pub fn extract(
+        &self,
+        image: &ImageMatrix,
+        landmarks: &[FaceLandmarks],
+    ) -> Result<Vec<RgbImage>, String> {
+        if let Some(face) = landmarks
+            .iter()
+            .find(|face| face.len() != 5 && face.len() != 68)
+        {
+            return Err(format!(
+                "Cannot align a face with {} landmarks, 5 or 68 are needed",
+                face.len()
+            ));
+        }
+
+        let num_faces = landmarks.len();
+        let size = self.size as usize;
+        // the C++ code below writes exactly this many bytes, so they must not overflow
+        let chip_len = size
+            .checked_mul(size)
+            .and_then(|len| len.checked_mul(3))
+            .ok_or_else(|| format!("Face chips of {} pixels do not fit in memory", size))?;
+        let buffer_len = chip_len
+            .checked_mul(num_faces)
+            .ok_or_else(|| format!("{} face chips do not fit in memory", num_faces))?;
+        let mut buffer = vec![0u8; buffer_len];
+
+        let mut chips = FaceChips::default();
+        self.push_chips(&mut chips, image, landmarks);
+
+        let chips = &chips;
+        let output = buffer.as_mut_ptr();
+
+        unsafe {
+            cpp!([
+                    chips as "std::vector<matrix<rgb_pixel>>*",
+                    output as "uint8_t*"
+                ] {
+
+                // chips are written one after another, row by row
+                size_t offset = 0;
+                for (auto& chip : *chips) {
+                    for (long y = 0; y < chip.nr(); y++) {
+                        for (long x = 0; x < chip.nc(); x++) {
+                            const rgb_pixel& pixel = chip(y, x);
+                            output[offset] = pixel.red;
+                            output[offset + 1] = pixel.green;
+                            output[offset + 2] = pixel.blue;
+                            offset += 3;
+                        }
+                    }
+                }
+            })
+        };
+
+        let chips = buffer
+            .chunks_exact(chip_len)
+            .map(|chip| RgbImage::from_raw(self.size, self.size, chip.to_vec()).unwrap())
+            .collect();
+
+        Ok(chips)
+    }
+
+    /// Appends chips of the faces in the image to `chips`, which must have 5 or 68 landmarks.
+    pub(crate) fn push_chips(
+        &self,
+        chips: &mut FaceChips,
+        image: &ImageMatrix,
+        landmarks: &[FaceLandmarks],
+    ) {
+        let num_faces = landmarks.len();
+        let landmarks = landmarks.as_ptr();
+        let size = self.size as usize;
+        let padding = self.padding;
+
+        unsafe {
+            cpp!([
+                    chips as "std::vector<matrix<rgb_pixel>>*",
+                    image as "matrix<rgb_pixel>*",
+                    landmarks as "full_object_detection*",
+                    num_faces as "size_t",
+                    size as "size_t",
+                    padding as "double"
+                ] {
+
+                std::vector<chip_details> dets;
+                dets.reserve(num_faces);
+
+                for (size_t offset = 0; offset < num_faces; offset++) {
+                    dets.push_back(get_face_chip_details(*(landmarks + offset), size, padding));
+                }
+
+                array<matrix<rgb_pixel>> face_chips;
+                extract_image_chips(*image, dets, face_chips);
+
+                for (auto& chip : face_chips) {
+                    chips->push_back(std::move(chip));
+                }
+            })
+        }
+    }
+}
+
+impl Default for FaceChipExtractor {
+    fn default() -> Self {
+        Self {
+            size: Self::DEFAULT_SIZE,
+            padding: Self::DEFAULT_PADDING,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{Point, Rectangle};
+    use image::Rgb;
+
+    /// Landmarks of a face in the middle of a 100x100 image, the first `count` of which are
+    /// the points of the 5 point model.
+    pub(crate) fn face_landmarks(count: usize) -> FaceLandmarks {
+        let rect = Rectangle {
+            left: 20,
+            top: 20,
+            right: 80,
+            bottom: 80,
+        };
+        // eye corners and the bottom of the nose of the 5 point model
+        let five = [(70, 40), (60, 40), (30, 40), (40, 40), (50, 60)];
+        let points: Vec<Point> = (0..count)
+            .map(|i| {
+                let (x, y) = five[i % five.len()];
+                Point { x, y }
+            })
+            .collect();
+
+        FaceLandmarks::new(&rect, &points)
+    }
+
+    #[test]
+    fn test_extract_chips() {
+        let image = RgbImage::from_pixel(100, 100, Rgb([200, 100, 50]));
+        let matrix = ImageMatrix::from_image(&image);
+        let extractor = FaceChipExtractor::new(64, 0.25).unwrap();
+
+        let chips = extractor
+            .extract(&matrix, &[face_landmarks(5), face_landmarks(5)])
+            .unwrap();
+
+        assert_eq!(chips.len(), 2);
+        assert_eq!(chips[0].dimensions(), (64, 64));
+        assert_eq!(*chips[1].get_pixel(32, 32), Rgb([200, 100, 50]));
+    }
+
+    #[test]
+    fn test_extract_rejects_other_models() {
+        let matrix = ImageMatrix::from_image(&RgbImage::new(100, 100));
+
+        assert!(
+            FaceChipExtractor::default()
+                .extract(&matrix, &[face_landmarks(3)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_parameters() {
+        assert!(FaceChipExtractor::new(0, 0.25).is_err());
+        assert!(FaceChipExtractor::new(FaceChipExtractor::MAX_SIZE + 1, 0.25).is_err());
+        assert!(FaceChipExtractor::new(150, -0.1).is_err());
+        assert!(FaceChipExtractor::new(150, f64::NAN).is_err());
+        assert!(FaceChipExtractor::new(FaceChipExtractor::MAX_SIZE, 0.0).is_ok());
+    }
+}
@@ -3,8 +3,26 @@ use std::ops::*;
 use std::path::*;
 use std::{fmt, slice};
 
+/// Detects faces, optionally in an image upsampled `upsample_times`.
+///
+/// Each upsampling doubles the image, which lets the detectors find faces smaller than
+/// about 80x80 pixels at the cost of speed. Rectangles are always in original image coordinates.
 pub trait FaceDetectorModel {
-    fn face_locations(&self, image: &ImageMatrix) -> FaceLocations;
+    fn face_locations(&self, image: &ImageMatrix, upsample_times: u32) -> FaceLocations;
+
+    /// Detect face rectangles together with the detector's confidence in each of them.
+    fn face_detections(&self, image: &ImageMatrix, upsample_times: u32) -> FaceDetections;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[repr(C)]
+/// A detected face.
+///
+/// The confidence is the detector's score, so it is only comparable between detections
+/// of the same detector. Higher is more confident.
+pub struct FaceDetection {
+    pub rect: Rectangle,
+    pub confidence: f64,
 }
 
 cpp_class!(unsafe struct FaceDetectorInner as "frontal_face_detector");
@@ -31,19 +49,63 @@ impl FaceDetector {
 
         Self { inner }
     }
+
+    /// Detect faces, keeping those scoring above `adjust_threshold`.
+    ///
+    /// Negative thresholds return more, less certain, detections.
+    pub fn face_detections_with_threshold(
+        &self,
+        image: &ImageMatrix,
+        upsample_times: u32,
+        adjust_threshold: f64,
+    ) -> FaceDetections {
+        let detector = &self.inner;
+
+        unsafe {
+            cpp!([detector as "frontal_face_detector*", image as "matrix<rgb_pixel>*", upsample_times as "unsigned int", adjust_threshold as "double"] -> FaceDetections as "std::vector<face_detection>" {
+                matrix<rgb_pixel> upsampled;
+                const matrix<rgb_pixel>& input = upsample_image(*image, upsample_times, upsampled);
+
+                std::vector<std::pair<double, rectangle>> detections;
+                (*detector)(input, detections, adjust_threshold);
+
+                std::vector<face_detection> results;
+                results.reserve(detections.size());
+
+                for (auto &detection: detections) {
+                    results.push_back(face_detection { downsample_rect(detection.second, upsample_times), detection.first });
+                }
+
+                return results;
+            })
+        }
+    }
 }
 
 impl FaceDetectorModel for FaceDetector {
     /// Detect face rectangles from an image.
-    fn face_locations(&self, image: &ImageMatrix) -> FaceLocations {
+    fn face_locations(&self, image: &ImageMatrix, upsample_times: u32) -> FaceLocations {
         let detector = &self.inner;
 
         unsafe {
-            cpp!([detector as "frontal_face_detector*", image as "matrix<rgb_pixel>*"] -> FaceLocations as "std::vector<rectangle>"  {
-                return (*detector)(*image);
+            cpp!([detector as "frontal_face_detector*", image as "matrix<rgb_pixel>*", upsample_times as "unsigned int"] -> FaceLocations as "std::vector<rectangle>"  {
+                matrix<rgb_pixel> upsampled;
+                const matrix<rgb_pixel>& input = upsample_image(*image, upsample_times, upsampled);
+
+                std::vector<rectangle> rects = (*detector)(input);
+
+                for (rectangle &rect: rects) {
+                    rect = downsample_rect(rect, upsample_times);
+                }
+
+                return rects;
             })
         }
     }
+
+    fn face_detections(&self, image: &ImageMatrix, upsample_times: u32) -> FaceDetections {
+        self.face_detections_with_threshold(image, upsample_times, 0.0)
+    }
 }
 
 impl Default for FaceDetector {
@@ -95,12 +157,15 @@ impl FaceDetectorCnn {
 }
 
 impl FaceDetectorModel for FaceDetectorCnn {
-    fn face_locations(&self, image: &ImageMatrix) -> FaceLocations {
+    fn face_locations(&self, image: &ImageMatrix, upsample_times: u32) -> FaceLocations {
         let detector = &self.inner;
 
         unsafe {
-            cpp!([detector as "face_detection_cnn*", image as "matrix<rgb_pixel>*"] -> FaceLocations as "std::vector<rectangle>" {
-                std::vector<mmod_rect> detections = (*detector)(*image);
+            cpp!([detector as "face_detection_cnn*", image as "matrix<rgb_pixel>*", upsample_times as "unsigned int"] -> FaceLocations as "std::vector<rectangle>" {
+                matrix<rgb_pixel> upsampled;
+                const matrix<rgb_pixel>& input = upsample_image(*image, upsample_times, upsampled);
+
+                std::vector<mmod_rect> detections = (*detector)(input);
 
                 // Convert from mmod rectangles
                 // see: https://github.com/davisking/dlib/blob/master/dlib/image_processing/full_object_detection.h#L132
@@ -110,13 +175,35 @@ impl FaceDetectorModel for FaceDetectorCnn {
                 rects.reserve(detections.size());
 
                 for (mmod_rect &detection: detections) {
-                    rects.push_back(detection.rect);
+                    rects.push_back(downsample_rect(detection.rect, upsample_times));
                 }
 
                 return rects;
             })
         }
     }
+
+    fn face_detections(&self, image: &ImageMatrix, upsample_times: u32) -> FaceDetections {
+        let detector = &self.inner;
+
+        unsafe {
+            cpp!([detector as "face_detection_cnn*", image as "matrix<rgb_pixel>*", upsample_times as "unsigned int"] -> FaceDetections as "std::vector<face_detection>" {
+                matrix<rgb_pixel> upsampled;
+                const matrix<rgb_pixel>& input = upsample_image(*image, upsample_times, upsampled);
+
+                std::vector<mmod_rect> detections = (*detector)(input);
+
+                std::vector<face_detection> results;
+                results.reserve(detections.size());
+
+                for (mmod_rect &detection: detections) {
+                    results.push_back(face_detection { downsample_rect(detection.rect, upsample_times), detection.detection_confidence });
+                }
+
+                return results;
+            })
+        }
+    }
 }
 
 cpp_class!(
@@ -154,6 +241,41 @@ impl fmt::Debug for FaceLocations {
     }
 }
 
+cpp_class!(
+    /// A rust wrapper around an ` std::vector < face_detection >`.
+    pub unsafe struct FaceDetections as "std::vector<face_detection>"
+);
+
+impl Deref for FaceDetections {
+    type Target = [FaceDetection];
+
+    fn deref(&self) -> &Self::Target {
+        let len = unsafe {
+            cpp!([self as "std::vector<face_detection>*"] -> usize as "size_t" {
+                return self->size();
+            })
+        };
+
+        if len == 0 {
+            &[]
+        } else {
+            unsafe {
+                let pointer = cpp!([self as "std::vector<face_detection>*"] -> *const FaceDetection as "face_detection*" {
+                    return &(*self)[0];
+                });
+
+                slice::from_raw_parts(pointer, len)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FaceDetections {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.deref().fmt(formatter)
+    }
+}
+
 #[test]
 fn face_detection_test() {
     use image;
@@ -161,7 +283,7 @@ fn face_detection_test() {
     let matrix = ImageMatrix::from_image(&image);
     let detector = FaceDetector::new();
 
-    let locations = detector.face_locations(&matrix);
+    let locations = detector.face_locations(&matrix, 0);
 
     assert_eq!(locations.len(), 1);
     assert_eq!(
@@ -174,3 +296,38 @@ fn face_detection_test() {
         }
     );
 }
+
+#[test]
+fn face_detections_test() {
+    use image;
+    let image = image::open("benches/obama_1.jpg").unwrap().to_rgb8();
+    let matrix = ImageMatrix::from_image(&image);
+    let detector = FaceDetector::new();
+
+    let detections = detector.face_detections(&matrix, 0);
+
+    assert_eq!(detections.len(), 1);
+    assert_eq!(detections[0].rect, detector.face_locations(&matrix, 0)[0]);
+    assert!(detections[0].confidence > 0.0);
+
+    // lowering the threshold can only add detections
+    let more = detector.face_detections_with_threshold(&matrix, 0, -1.0);
+    assert!(more.len() >= detections.len());
+}
+
+#[test]
+fn face_detection_upsampled_test() {
+    use image;
+    let image = image::open("benches/obama_1.jpg").unwrap().to_rgb8();
+    let matrix = ImageMatrix::from_image(&image);
+    let detector = FaceDetector::new();
+
+    let locations = detector.face_locations(&matrix, 1);
+
+    // the face is found in the upsampled image, but reported in original coordinates
+    assert_eq!(locations.len(), 1);
+    assert!((locations[0].left as i64 - 305).abs() < 20);
+    assert!((locations[0].top as i64 - 113).abs() < 20);
+    assert!((locations[0].right as i64 - 520).abs() < 20);
+    assert!((locations[0].bottom as i64 - 328).abs() < 20);
+}
@@ -1,5 +1,6 @@
 //! Face encoding structs.
 
+use crate::face_chip::FaceChipExtractor;
 use crate::landmark_prediction::FaceLandmarks;
 use crate::{ImageMatrix, path_as_cstring};
 use std::fmt;
@@ -50,52 +51,66 @@ impl FaceEncodingNetwork {
     /// Get a number of face encodings from an image and a list of landmarks, and jitter them a certain amount.
     ///
     /// It is recommended to keep `num_jitters` at 0 unless you know what you're doing.
+    /// `batch_size` is the number of face chips the network processes at once.
     pub fn get_face_encodings(
         &self,
         image: &ImageMatrix,
         landmarks: &[FaceLandmarks],
         num_jitters: u32,
+        batch_size: usize,
+    ) -> FaceEncodings {
+        self.get_face_encodings_batch(&[(image, landmarks)], num_jitters, batch_size)
+    }
+
+    /// Like [`Self::get_face_encodings`], but for faces in many images at once.
+    ///
+    /// Chips of all the faces go through the network together, which is much faster than
+    /// encoding images with one or two faces one by one. The encodings are in the order of
+    /// the images and then of their landmarks.
+    pub fn get_face_encodings_batch(
+        &self,
+        faces: &[(&ImageMatrix, &[FaceLandmarks])],
+        num_jitters: u32,
+        batch_size: usize,
+    ) -> FaceEncodings {
+        let mut chips = FaceChips::default();
+        for (image, landmarks) in faces {
+            chips.push_faces(image, landmarks);
+        }
+
+        self.encode_chips(&chips, num_jitters, batch_size)
+    }
+
+    /// Encodes chips extracted from any number of images.
+    pub fn encode_chips(
+        &self,
+        chips: &FaceChips,
+        num_jitters: u32,
+        batch_size: usize,
     ) -> FaceEncodings {
-        let num_faces = landmarks.len();
-        let landmarks = landmarks.as_ptr();
         let net = &self.inner;
 
         unsafe {
             cpp!([
                     net as "face_encoding_nn*",
-                    image as "matrix<rgb_pixel>*",
-                    landmarks as "full_object_detection*",
-                    num_faces as "size_t",
-                    num_jitters as "uint"
+                    chips as "std::vector<matrix<rgb_pixel>>*",
+                    num_jitters as "uint",
+                    batch_size as "size_t"
                 ] -> FaceEncodings as "std::vector<matrix<double,0,1>>" {
 
                 std::vector<matrix<double,0,1>> encodings;
-                encodings.reserve(num_faces);
-
-                // first we need to use the landmarks to get image chips for each face
-
-                std::vector<chip_details> dets;
-                dets.reserve(num_faces);
-
-                array<matrix<rgb_pixel>> face_chips;
-
-                for (size_t offset = 0; offset < num_faces; offset++) {
-                    chip_details details = get_face_chip_details(*(landmarks + offset), 150, 0.25);
-                    dets.push_back(details);
-                }
-
-                extract_image_chips(*image, dets, face_chips);
+                encodings.reserve(chips->size());
 
                 // extract descriptors and convert from float vectors to double vectors
 
                 if (num_jitters <= 1) {
-                    auto network_output = (*net)(face_chips, 16);
+                    auto network_output = (*net)(*chips, batch_size);
                     for (matrix<float,0,1>& float_encoding: network_output) {
                         encodings.push_back((matrix_cast<double>(float_encoding)));
                     }
                 } else {
-                    for (auto& chip : face_chips) {
-                        auto network_output = (*net)(jitter_image(chip, num_jitters), 16);
+                    for (auto& chip : *chips) {
+                        auto network_output = (*net)(jitter_image(chip, num_jitters), batch_size);
                         matrix<float,0,1> float_encoding = mean(mat(network_output));
 
                         encodings.push_back(matrix_cast<double>(float_encoding));
@@ -108,6 +123,30 @@ impl FaceEncodingNetwork {
     }
 }
 
+cpp_class!(
+    /// A wrapper around a `std::vector<matrix<rgb_pixel>>`, aligned face images ready to be encoded.
+    pub unsafe struct FaceChips as "std::vector<matrix<rgb_pixel>>"
+);
+
+impl FaceChips {
+    /// Appends chips of the faces in the image, aligned the way the encoding network expects.
+    pub fn push_faces(&mut self, image: &ImageMatrix, landmarks: &[FaceLandmarks]) {
+        FaceChipExtractor::default().push_chips(self, image, landmarks);
+    }
+
+    pub fn len(&self) -> usize {
+        unsafe {
+            cpp!([self as "std::vector<matrix<rgb_pixel>>*"] -> usize as "size_t" {
+                return self->size();
+            })
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 cpp_class!(
     /// A wrapper around a `std::vector<matrix<double,0,1>>`, a vector of encodings.
     pub unsafe struct FaceEncodings as "std::vector<matrix<double,0,1>>"
@@ -350,6 +389,22 @@ mod tests {
         assert_eq!(encodings.get(0), None);
     }
 
+    #[test]
+    fn test_face_chips_from_many_images() {
+        use crate::face_chip::tests::face_landmarks;
+        use image::RgbImage;
+
+        let first = ImageMatrix::from_image(&RgbImage::new(100, 100));
+        let second = ImageMatrix::from_image(&RgbImage::new(120, 90));
+
+        let mut chips = FaceChips::default();
+        assert!(chips.is_empty());
+
+        chips.push_faces(&first, &[face_landmarks(5), face_landmarks(5)]);
+        chips.push_faces(&second, &[face_landmarks(5)]);
+        assert_eq!(chips.len(), 3);
+    }
+
     #[test]
     fn test_sizes() {
         use std::mem::*;
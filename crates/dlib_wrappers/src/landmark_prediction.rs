@@ -6,9 +6,20 @@ use crate::{ImageMatrix, Point, Rectangle, path_as_cstring};
 use std::ops::*;
 use std::slice;
 
+/// Predicts positions of face landmarks, e.g. eye corners, within a detected face.
+pub trait LandmarkModel {
+    fn face_landmarks(&self, image: &ImageMatrix, rect: &Rectangle) -> FaceLandmarks;
+
+    /// Number of landmarks predicted for every face, e.g. 5 or 68 for the dlib models.
+    fn num_parts(&self) -> usize;
+}
+
 cpp_class!(unsafe struct LandmarkPredictorInner as "shape_predictor");
 
 /// A face landmark predictor.
+///
+/// Works with any dlib shape predictor, e.g. `shape_predictor_68_face_landmarks.dat`
+/// or the much smaller and faster `shape_predictor_5_face_landmarks.dat`.
 #[derive(Clone)]
 pub struct LandmarkPredictor {
     inner: LandmarkPredictorInner,
@@ -44,11 +55,13 @@ impl LandmarkPredictor {
             Ok(Self { inner })
         }
     }
+}
 
+impl LandmarkModel for LandmarkPredictor {
     /// Detect face landmarks.
     ///
     /// This will generally always return the number of landmarks as defined by the model.
-    pub fn face_landmarks(&self, image: &ImageMatrix, rect: &Rectangle) -> FaceLandmarks {
+    fn face_landmarks(&self, image: &ImageMatrix, rect: &Rectangle) -> FaceLandmarks {
         let predictor = &self.inner;
 
         unsafe {
@@ -57,6 +70,16 @@ impl LandmarkPredictor {
             })
         }
     }
+
+    fn num_parts(&self) -> usize {
+        let predictor = &self.inner;
+
+        unsafe {
+            cpp!([predictor as "shape_predictor*"] -> usize as "size_t" {
+                return predictor->num_parts();
+            })
+        }
+    }
 }
 
 // https://github.com/davisking/dlib/blob/master/dlib/image_processing/full_object_detection.h#L21
@@ -90,11 +113,177 @@ impl Deref for FaceLandmarks {
     }
 }
 
-#[test]
-fn test_default_landmarks() {
-    // ensure that FaceLandmarks::default() doesnt allow memory violations in safe code
-    let landmarks = FaceLandmarks::default();
-    assert!(landmarks.is_empty());
-    assert_eq!(landmarks.len(), 0);
-    assert_eq!(landmarks.get(0), None);
+impl FaceLandmarks {
+    /// Landmarks of a face found earlier, e.g. read back from storage.
+    pub fn new(rect: &Rectangle, points: &[Point]) -> Self {
+        let len = points.len();
+        let points = points.as_ptr();
+
+        unsafe {
+            cpp!([rect as "rectangle*", points as "point*", len as "size_t"] -> FaceLandmarks as "full_object_detection" {
+                std::vector<point> parts(points, points + len);
+                return full_object_detection(*rect, parts);
+            })
+        }
+    }
+
+    /// Named groups of the landmarks, available only for 68 point models.
+    pub fn as_68_points(&self) -> Result<Landmarks68<'_>, String> {
+        Landmarks68::new(self)
+    }
+}
+
+/// Landmarks of the 68 point dlib model, grouped by the part of the face they outline.
+///
+/// "Left" and "right" are as seen in the image, i.e. the left eye is the person's right eye.
+/// Index ranges follow the iBUG 300-W annotation the dlib model was trained on.
+#[derive(Debug, Copy, Clone)]
+pub struct Landmarks68<'a> {
+    points: &'a [Point],
+}
+
+impl<'a> Landmarks68<'a> {
+    /// Wraps the points, failing for other models, e.g. the 5 point one.
+    pub fn new(points: &'a [Point]) -> Result<Self, String> {
+        if points.len() != 68 {
+            return Err(format!(
+                "Expected 68 face landmarks, but the model predicted {}",
+                points.len()
+            ));
+        }
+
+        Ok(Self { points })
+    }
+
+    /// 17 points along the chin, from the left ear to the right one.
+    pub fn jawline(&self) -> &'a [Point] {
+        &self.points[0..17]
+    }
+
+    pub fn left_eyebrow(&self) -> &'a [Point] {
+        &self.points[17..22]
+    }
+
+    pub fn right_eyebrow(&self) -> &'a [Point] {
+        &self.points[22..27]
+    }
+
+    /// 4 points from between the eyes down to the tip of the nose.
+    pub fn nose_bridge(&self) -> &'a [Point] {
+        &self.points[27..31]
+    }
+
+    /// 5 points along the bottom of the nose.
+    pub fn nose_tip(&self) -> &'a [Point] {
+        &self.points[31..36]
+    }
+
+    /// 6 points around the eye, clockwise from its corner on the left of the image, i.e.
+    /// its outer corner.
+    pub fn left_eye(&self) -> &'a [Point] {
+        &self.points[36..42]
+    }
+
+    /// 6 points around the eye, clockwise from its corner on the left of the image, i.e.
+    /// its inner corner.
+    pub fn right_eye(&self) -> &'a [Point] {
+        &self.points[42..48]
+    }
+
+    pub fn outer_lips(&self) -> &'a [Point] {
+        &self.points[48..60]
+    }
+
+    pub fn inner_lips(&self) -> &'a [Point] {
+        &self.points[60..68]
+    }
+
+    pub fn left_eye_center(&self) -> (f64, f64) {
+        centroid(self.left_eye())
+    }
+
+    pub fn right_eye_center(&self) -> (f64, f64) {
+        centroid(self.right_eye())
+    }
+
+    pub fn mouth_center(&self) -> (f64, f64) {
+        centroid(self.outer_lips())
+    }
+}
+
+/// The average position of the points, `(NaN, NaN)` when there are none.
+pub fn centroid(points: &[Point]) -> (f64, f64) {
+    let count = points.len() as f64;
+    let (sum_x, sum_y) = points.iter().fold((0.0, 0.0), |(sum_x, sum_y), point| {
+        (sum_x + point.x as f64, sum_y + point.y as f64)
+    });
+
+    (sum_x / count, sum_y / count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(count: i64) -> Vec<Point> {
+        (0..count).map(|i| Point { x: i, y: 2 * i }).collect()
+    }
+
+    #[test]
+    fn test_default_landmarks() {
+        // ensure that FaceLandmarks::default() doesnt allow memory violations in safe code
+        let landmarks = FaceLandmarks::default();
+        assert!(landmarks.is_empty());
+        assert_eq!(landmarks.len(), 0);
+        assert_eq!(landmarks.get(0), None);
+    }
+
+    #[test]
+    fn test_landmarks_68_groups() {
+        let points = points(68);
+        let landmarks = Landmarks68::new(&points).unwrap();
+
+        assert_eq!(landmarks.jawline().len(), 17);
+        assert_eq!(landmarks.left_eyebrow().len(), 5);
+        assert_eq!(landmarks.right_eyebrow().len(), 5);
+        assert_eq!(landmarks.nose_bridge().len(), 4);
+        assert_eq!(landmarks.nose_tip().len(), 5);
+        assert_eq!(landmarks.left_eye().len(), 6);
+        assert_eq!(landmarks.right_eye().len(), 6);
+        assert_eq!(landmarks.outer_lips().len(), 12);
+        assert_eq!(landmarks.inner_lips().len(), 8);
+
+        assert_eq!(landmarks.left_eye()[0], Point { x: 36, y: 72 });
+        assert_eq!(landmarks.inner_lips()[7], Point { x: 67, y: 134 });
+        assert_eq!(landmarks.left_eye_center(), (38.5, 77.0));
+    }
+
+    #[test]
+    fn test_landmarks_68_rejects_5_points() {
+        let points = points(5);
+
+        assert!(Landmarks68::new(&points).is_err());
+    }
+
+    #[test]
+    fn test_landmarks_from_points() {
+        let rect = Rectangle {
+            left: 1,
+            top: 2,
+            right: 3,
+            bottom: 4,
+        };
+        let points = points(5);
+        let landmarks = FaceLandmarks::new(&rect, &points);
+
+        assert_eq!(&*landmarks, points.as_slice());
+    }
+
+    #[test]
+    fn test_centroid() {
+        let points = [Point { x: 0, y: 0 }, Point { x: 4, y: 2 }];
+
+        assert_eq!(centroid(&points), (2.0, 1.0));
+        assert!(centroid(&[]).0.is_nan());
+    }
 }
@@ -18,6 +18,8 @@
 extern crate cpp;
 extern crate image;
 
+pub mod clustering;
+pub mod face_chip;
 pub mod face_detection;
 pub mod face_encoding;
 pub mod image_matrix;
@@ -58,6 +60,8 @@ pub struct Rectangle {
 cpp! {{
     #include <dlib/image_processing/frontal_face_detector.h>
     #include <dlib/image_processing/full_object_detection.h>
+    #include <dlib/image_transforms.h>
+    #include <dlib/clustering.h>
     #include <dlib/dnn.h>
 
     using namespace dlib;
@@ -104,6 +108,33 @@ cpp! {{
 
     using face_detection_cnn = loss_mmod<con<1,9,9,1,1,rcon5<rcon5<rcon5<downsampler<input_rgb_image_pyramid<pyramid_down<6>>>>>>>>;
 
+    // layout shared with `face_detection::FaceDetection`
+    struct face_detection {
+        rectangle rect;
+        double confidence;
+    };
+
+    // each level of pyramid_up doubles the image, so faces too small for the detectors
+    // become detectable; returns `image` itself when there is nothing to upsample
+    const matrix<rgb_pixel>& upsample_image(const matrix<rgb_pixel>& image, unsigned int upsample_times, matrix<rgb_pixel>& upsampled) {
+        if (upsample_times == 0) {
+            return image;
+        }
+
+        upsampled = image;
+        pyramid_down<2> pyr;
+        for (unsigned int i = 0; i < upsample_times; ++i) {
+            pyramid_up(upsampled, pyr);
+        }
+        return upsampled;
+    }
+
+    // maps a rectangle found in an upsampled image back to the original one
+    rectangle downsample_rect(const rectangle& rect, unsigned int upsample_times) {
+        pyramid_down<2> pyr;
+        return pyr.rect_down(rect, upsample_times);
+    }
+
     // https://github.com/davisking/dlib/blob/master/tools/python/src/face_recognition.cpp#L131
     std::vector<matrix<rgb_pixel>> jitter_image(const matrix<rgb_pixel>& img, const int num_jitters) {
         dlib::rand local_rnd;
@@ -121,7 +152,7 @@ fn test_default_image() {
 
     let matrix = ImageMatrix::default();
     let face_det = FaceDetector::default();
-    let locations = face_det.face_locations(&matrix);
+    let locations = face_det.face_locations(&matrix, 0);
     assert!(locations.is_empty());
     assert_eq!(locations.len(), 0);
     assert_eq!(locations.get(0), None);
@@ -0,0 +1,85 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Reasons why recognizing faces in a file, or accessing the registry, failed.
+#[derive(Debug)]
+pub enum RecognizeError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Decode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Db(sqlx::Error),
+    Model(String),
+}
+
+impl RecognizeError {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Failures reported by `image` while opening a file are IO errors rather than decoding ones.
+    pub(crate) fn image(path: &Path, source: image::ImageError) -> Self {
+        match source {
+            image::ImageError::IoError(source) => Self::io(path, source),
+            source => Self::Decode {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+}
+
+impl RecognizeError {
+    /// The file the error is about, if any.
+    pub(crate) fn path(&self) -> Option<&Path> {
+        match self {
+            RecognizeError::Io { path, .. } => Some(path),
+            RecognizeError::Decode { path, .. } => Some(path),
+            RecognizeError::Db(_) | RecognizeError::Model(_) => None,
+        }
+    }
+}
+
+impl Display for RecognizeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecognizeError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            RecognizeError::Decode { path, source } => {
+                write!(f, "cannot decode {}: {}", path.display(), source)
+            }
+            RecognizeError::Db(source) => write!(f, "database error: {}", source),
+            RecognizeError::Model(message) => write!(f, "model error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RecognizeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RecognizeError::Io { source, .. } => Some(source),
+            RecognizeError::Decode { source, .. } => Some(source),
+            RecognizeError::Db(source) => Some(source),
+            RecognizeError::Model(_) => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for RecognizeError {
+    fn from(source: sqlx::Error) -> Self {
+        RecognizeError::Db(source)
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for RecognizeError {
+    fn from(source: sqlx::migrate::MigrateError) -> Self {
+        RecognizeError::Db(sqlx::Error::Migrate(Box::new(source)))
+    }
+}
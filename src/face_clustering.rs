@@ -0,0 +1,66 @@
+use crate::error::RecognizeError;
+use crate::person_registry::person_registry_sqlite::PersonRegistrySqlite;
+use dlib_wrappers::clustering::chinese_whispers;
+use std::collections::HashMap;
+use std::time::Instant;
+use tracing::info;
+
+const CHINESE_WHISPERS_ITERATIONS: usize = 100;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ClusterOptions {
+    /// Number of nearest faces each face is connected to in the similarity graph.
+    pub(crate) neighbours: u32,
+    /// Faces further apart than this are never connected.
+    pub(crate) max_distance: f32,
+    /// Smaller clusters are not stored.
+    pub(crate) min_size: usize,
+    /// Faces of lower quality are left out of the graph, so they neither form nor join clusters.
+    pub(crate) min_quality: Option<f64>,
+}
+
+/// Groups all unlabeled faces into candidate identities and stores the cluster ids.
+///
+/// Returns face ids of the stored clusters, largest first, so that the cluster with
+/// index `i` has cluster id `i + 1`.
+pub async fn cluster_unlabeled_faces(
+    registry: &PersonRegistrySqlite,
+    options: ClusterOptions,
+) -> Result<Vec<Vec<i64>>, RecognizeError> {
+    let start = Instant::now();
+
+    let (face_ids, edges) = registry
+        .unlabeled_face_graph(
+            options.neighbours,
+            options.max_distance,
+            options.min_quality,
+        )
+        .await?;
+
+    info!(
+        "built a graph of {} faces and {} edges in {:?}",
+        face_ids.len(),
+        edges.len(),
+        start.elapsed()
+    );
+
+    let labels = chinese_whispers(face_ids.len(), &edges, CHINESE_WHISPERS_ITERATIONS);
+
+    let mut clusters: HashMap<u64, Vec<i64>> = HashMap::new();
+    for (face_id, label) in face_ids.into_iter().zip(labels) {
+        clusters.entry(label).or_default().push(face_id);
+    }
+
+    let mut clusters: Vec<Vec<i64>> = clusters
+        .into_values()
+        .filter(|faces| faces.len() >= options.min_size)
+        .collect();
+
+    clusters.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+
+    registry.set_clusters(&clusters).await?;
+
+    info!("clustering finished in {:?}", start.elapsed());
+
+    Ok(clusters)
+}
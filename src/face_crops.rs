@@ -0,0 +1,101 @@
+//! Writing aligned images of stored faces, e.g. to build datasets or thumbnails.
+
+use crate::error::RecognizeError;
+use crate::face_recognizer::FaceRecognizer;
+use crate::person_registry::person_registry_sqlite::{PersonFace, PersonRegistrySqlite};
+use dlib_wrappers::face_chip::FaceChipExtractor;
+use dlib_wrappers::landmark_prediction::FaceLandmarks;
+use dlib_wrappers::{ImageMatrix, Point, Rectangle};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::task;
+use tracing::{info, warn};
+
+/// A face to cut out of its photo.
+struct CropFace {
+    face_id: i64,
+    rect: Rectangle,
+    landmarks: Vec<Point>,
+}
+
+/// Writes an aligned chip of every face into `output_dir`, named after the face id.
+///
+/// Faces stored without landmarks and photos which cannot be read any more are skipped.
+/// Returns the number of chips written.
+pub(crate) async fn write_face_crops(
+    registry: &PersonRegistrySqlite,
+    faces: Vec<PersonFace>,
+    extractor: FaceChipExtractor,
+    output_dir: &Path,
+) -> Result<usize, RecognizeError> {
+    // faces in the same photo are cut out of a single decoded image
+    let mut faces_by_path: BTreeMap<PathBuf, Vec<CropFace>> = BTreeMap::new();
+    for face in faces {
+        let Some(path) = face.path else {
+            warn!("face {} has no photo, skipping", face.face_id);
+            continue;
+        };
+        let Some(landmarks) = registry.find_landmarks(face.face_id).await? else {
+            warn!(
+                "face {} has no landmarks stored, recognize its photo again to crop it",
+                face.face_id
+            );
+            continue;
+        };
+
+        faces_by_path
+            .entry(PathBuf::from(path))
+            .or_default()
+            .push(CropFace {
+                face_id: face.face_id,
+                rect: face.rect,
+                landmarks,
+            });
+    }
+
+    let mut written = 0;
+    for (path, faces) in faces_by_path {
+        let output_dir = output_dir.to_path_buf();
+        // decoding and encoding images would stall the other tasks
+        written +=
+            task::spawn_blocking(move || write_photo_crops(&path, &faces, extractor, &output_dir))
+                .await
+                .unwrap()?;
+    }
+
+    Ok(written)
+}
+
+fn write_photo_crops(
+    path: &Path,
+    faces: &[CropFace],
+    extractor: FaceChipExtractor,
+    output_dir: &Path,
+) -> Result<usize, RecognizeError> {
+    // the photo may have been moved or deleted since it was recognized
+    let image = match FaceRecognizer::open_image(path) {
+        Ok(image) => image,
+        Err(err) => {
+            warn!("skipping {} faces: {}", faces.len(), err);
+            return Ok(0);
+        }
+    };
+
+    let matrix = ImageMatrix::from_image(&image.rgb);
+    let landmarks: Vec<FaceLandmarks> = faces
+        .iter()
+        .map(|face| FaceLandmarks::new(&face.rect, &face.landmarks))
+        .collect();
+    let chips = extractor
+        .extract(&matrix, &landmarks)
+        .map_err(RecognizeError::Model)?;
+
+    for (face, chip) in faces.iter().zip(chips.iter()) {
+        let output = output_dir.join(format!("{}.png", face.face_id));
+        chip.save(&output)
+            .map_err(|err| RecognizeError::image(&output, err))?;
+        info!("face {} saved to {}", face.face_id, output.display());
+    }
+
+    Ok(chips.len())
+}
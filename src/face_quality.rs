@@ -0,0 +1,107 @@
+//! Scoring how useful a detected face is for recognition.
+//!
+//! The score combines the size of the face, the detector's confidence, the sharpness and
+//! brightness of the aligned face and the head pose into a single number between 0 and 1.
+
+use crate::face_recognizer::LocatedFace;
+use crate::head_pose::HeadPose;
+use dlib_wrappers::face_chip::FaceChipExtractor;
+use dlib_wrappers::landmark_prediction::FaceLandmarks;
+use dlib_wrappers::{ImageMatrix, Rectangle};
+use image::GrayImage;
+use image::imageops::grayscale;
+
+/// Faces with the shorter side at least this many pixels long get the full size score.
+const FULL_SCORE_SIZE: f64 = 120.0;
+
+/// Variance of the Laplacian of a chip above which it is considered sharp.
+const SHARP_VARIANCE: f64 = 250.0;
+
+/// Faces turned this many degrees or more get no pose score.
+const MAX_POSE_ANGLE: f64 = 60.0;
+
+/// Quality of the face between 0 and 1, where faces below about 0.3 are usually too small,
+/// blurry, dark or turned away to be recognized reliably.
+///
+/// Sharpness and brightness are measured on the chip the face encoding is calculated from,
+/// so they do not depend on the resolution of the face or on what surrounds it. The partial
+/// scores are combined with a geometric mean, so one poor aspect is enough to make the whole
+/// face poor. The pose counts only when it was estimated.
+pub(crate) fn face_quality(image: &ImageMatrix, face: &LocatedFace) -> f64 {
+    let Some(chip) = face_chip(image, face) else {
+        return 0.0;
+    };
+
+    let mut scores = vec![
+        size_score(&face.rect),
+        confidence_score(face.confidence),
+        sharpness_score(&chip),
+        brightness_score(&chip),
+    ];
+    if let Some(pose) = &face.pose {
+        scores.push(pose_score(pose));
+    }
+
+    let log_sum: f64 = scores.iter().map(|score| score.max(1e-6).ln()).sum();
+    (log_sum / scores.len() as f64).exp()
+}
+
+/// The aligned face in grayscale, `None` for landmarks which cannot be aligned.
+fn face_chip(image: &ImageMatrix, face: &LocatedFace) -> Option<GrayImage> {
+    let landmarks = FaceLandmarks::new(&face.rect, &face.landmarks);
+    let chips = FaceChipExtractor::default()
+        .extract(image, &[landmarks])
+        .ok()?;
+
+    chips.first().map(grayscale)
+}
+
+fn size_score(rect: &Rectangle) -> f64 {
+    let width = rect.right as i64 - rect.left as i64;
+    let height = rect.bottom as i64 - rect.top as i64;
+
+    (width.min(height) as f64 / FULL_SCORE_SIZE).clamp(0.0, 1.0)
+}
+
+/// Scores of both detectors are mostly between 0 and 2, and faces above 1 are certain.
+fn confidence_score(confidence: f64) -> f64 {
+    1.0 - (-2.0 * confidence.max(0.0)).exp()
+}
+
+/// Variance of the Laplacian, which is low when the chip has few edges, i.e. is blurry.
+fn sharpness_score(chip: &GrayImage) -> f64 {
+    let (width, height) = chip.dimensions();
+    let value = |x: u32, y: u32| chip.get_pixel(x, y)[0] as f64;
+
+    let mut sum = 0.0;
+    let mut sum_of_squares = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = value(x - 1, y) + value(x + 1, y) + value(x, y - 1) + value(x, y + 1)
+                - 4.0 * value(x, y);
+            sum += laplacian;
+            sum_of_squares += laplacian * laplacian;
+        }
+    }
+
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+    let variance = sum_of_squares / count - mean * mean;
+
+    (variance / SHARP_VARIANCE).clamp(0.0, 1.0)
+}
+
+/// Highest for mid-grey faces, falling to 0 for black and white ones.
+fn brightness_score(chip: &GrayImage) -> f64 {
+    let mean = chip.pixels().map(|pixel| pixel[0] as f64).sum::<f64>()
+        / (chip.width() * chip.height()) as f64;
+    let offset = (mean - 127.5) / 127.5;
+
+    1.0 - offset * offset
+}
+
+fn pose_score(pose: &HeadPose) -> f64 {
+    let angle = pose.yaw.abs().max(pose.pitch.abs());
+
+    (1.0 - angle / MAX_POSE_ANGLE).clamp(0.0, 1.0)
+}
@@ -1,29 +1,99 @@
-use crate::DefaultModels;
+use crate::error::RecognizeError;
+use crate::face_quality::face_quality;
+use crate::get_output_path;
+use crate::head_pose::{HeadPose, estimate_head_pose};
+use crate::image_helpers::{Rotation, annotate_face, overlap, scale_rectangle};
+use crate::models::{DefaultModels, Detector, DetectorMode};
 use crate::otel::{HISTOGRAM_F_D, HISTOGRAM_F_E, HISTOGRAM_L_P};
-use crate::person_registry::person_registry_sqlite::{PersonRegistrySqlite, ProcessedFileInsert};
+use crate::person_registry::person_registry_sqlite::{
+    FaceInsert, MAX_KNN_K, NearestFace, NearestQuery, PersonRegistrySqlite, ProcessedFileInsert,
+};
 use blake3::Hash;
-use dlib_wrappers::face_detection::{FaceDetectorModel, FaceLocations};
-use dlib_wrappers::face_encoding::FaceEncodings;
-use dlib_wrappers::landmark_prediction::FaceLandmarks;
-use dlib_wrappers::{ImageMatrix, Rectangle};
-use image::{RgbImage, open};
+use dlib_wrappers::face_detection::{FaceDetections, FaceDetectorModel};
+use dlib_wrappers::face_encoding::{FaceEncoding, FaceEncodings};
+use dlib_wrappers::landmark_prediction::{FaceLandmarks, LandmarkModel};
+use dlib_wrappers::{ImageMatrix, Point, Rectangle};
+use image::imageops::{FilterType, resize};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader, Rgb, RgbImage};
 use memmap2::Mmap;
 use opentelemetry::KeyValue;
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
+use std::fs;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
-use tracing::{info, instrument};
+use tokio::task;
+use tracing::{info, instrument, warn};
+
+const ANNOTATION_COLOUR: Rgb<u8> = Rgb([0, 255, 0]);
+
+/// Faces found in different rotations overlapping more than this are the same face.
+const DUPLICATE_FACE_OVERLAP: f64 = 0.5;
 
 pub struct FaceRecognizer {
     models: Arc<DefaultModels>,
     person_registry: PersonRegistrySqlite,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct FaceRecognizerOptions {
     pub(crate) skip_processed_check: bool,
+    /// Number of nearest labeled faces taking part in the vote for a new face's identity.
+    pub(crate) match_neighbours: u32,
+    /// Labeled faces further away than this are ignored when voting.
+    pub(crate) match_max_distance: f32,
+    /// Faces turned more than this many degrees are stored, but not matched.
+    pub(crate) match_max_angle: Option<f64>,
+    /// Faces of lower quality are stored, but not matched.
+    pub(crate) match_min_quality: Option<f64>,
+    /// Where to write copies of the images with the faces outlined, if anywhere.
+    pub(crate) annotate: Option<AnnotateTarget>,
+    pub(crate) detection: DetectionOptions,
+    pub(crate) encoding: EncodingOptions,
+}
+
+/// Parameters of finding faces in an image.
+#[derive(Clone, Debug, Default)]
+pub struct DetectionOptions {
+    /// Faces the detector is less confident about are dropped.
+    pub(crate) min_confidence: Option<f64>,
+    /// How many times the image is doubled before detection, to find small faces.
+    pub(crate) upsample_times: u32,
+    /// Larger images are downscaled to this width or height for detection only.
+    pub(crate) max_dimension: Option<u32>,
+    /// When no faces are found, look for them in the image rotated by right angles.
+    pub(crate) retry_rotations: bool,
+}
+
+/// Parameters of calculating face encodings.
+#[derive(Clone, Debug)]
+pub struct EncodingOptions {
+    /// How many randomly jittered copies of each face are encoded and averaged; slower, but
+    /// more accurate. 0 and 1 encode the face as it is.
+    pub(crate) num_jitters: u32,
+    /// Number of faces the network encodes at once.
+    pub(crate) batch_size: usize,
+}
+
+impl Default for EncodingOptions {
+    fn default() -> Self {
+        Self {
+            num_jitters: 0,
+            batch_size: 16,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum AnnotateTarget {
+    /// Next to the input image, with a `_new` suffix.
+    NextToInput,
+    /// Into the `output` directory, at the path of the input image relative to `root`, so
+    /// that images of the same name in different directories do not overwrite each other.
+    Directory { output: PathBuf, root: PathBuf },
 }
 
 impl Debug for FaceRecognizer {
@@ -32,6 +102,7 @@ impl Debug for FaceRecognizer {
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum DetectKind {
     AllFacesKnown(Vec<i64>),
     SomeFacesKnown {
@@ -41,10 +112,118 @@ pub(crate) enum DetectKind {
     NoFacesKnown(Vec<i64>),
 }
 
+impl DetectKind {
+    pub(crate) fn known_ids(&self) -> &[i64] {
+        match self {
+            DetectKind::AllFacesKnown(known_ids) => known_ids,
+            DetectKind::SomeFacesKnown { known_ids, .. } => known_ids,
+            DetectKind::NoFacesKnown(_) => &[],
+        }
+    }
+
+    pub(crate) fn unknown_ids(&self) -> &[i64] {
+        match self {
+            DetectKind::AllFacesKnown(_) => &[],
+            DetectKind::SomeFacesKnown { unknown_ids, .. } => unknown_ids,
+            DetectKind::NoFacesKnown(unknown_ids) => unknown_ids,
+        }
+    }
+}
+
+pub(crate) struct QueryFaceMatches {
+    /// Location of the face in the query image.
+    pub(crate) rect: Rectangle,
+    /// Stored faces sorted by ascending distance.
+    pub(crate) matches: Vec<NearestFace>,
+}
+
+/// A face found in an image, before it is encoded.
+pub(crate) struct LocatedFace {
+    pub(crate) rect: Rectangle,
+    /// Score of the detector which found the face.
+    pub(crate) confidence: f64,
+    pub(crate) detector: Detector,
+    /// Rotation of the image in which the face was found.
+    pub(crate) rotation: Rotation,
+    pub(crate) landmarks: Vec<Point>,
+    /// Estimated from the landmarks when there are 68 of them.
+    pub(crate) pose: Option<HeadPose>,
+    /// Between 0 and 1, see [`face_quality`].
+    pub(crate) quality: f64,
+}
+
+impl LocatedFace {
+    fn encoded(self, encoding: FaceEncoding, num_jitters: u32) -> DetectedFace {
+        DetectedFace {
+            rect: self.rect,
+            confidence: self.confidence,
+            detector: self.detector,
+            rotation: self.rotation,
+            landmarks: self.landmarks,
+            encoding,
+            num_jitters,
+            pose: self.pose,
+            quality: self.quality,
+        }
+    }
+}
+
+/// A face found in an image by the models, not stored yet.
+pub(crate) struct DetectedFace {
+    pub(crate) rect: Rectangle,
+    /// Score of the detector which found the face.
+    pub(crate) confidence: f64,
+    pub(crate) detector: Detector,
+    /// Rotation of the image in which the face was found.
+    pub(crate) rotation: Rotation,
+    pub(crate) landmarks: Vec<Point>,
+    pub(crate) encoding: FaceEncoding,
+    /// Number of jittered copies averaged into the encoding.
+    pub(crate) num_jitters: u32,
+    /// Estimated from the landmarks when there are 68 of them.
+    pub(crate) pose: Option<HeadPose>,
+    /// Between 0 and 1, see [`face_quality`].
+    pub(crate) quality: f64,
+}
+
+/// An image decoded and turned the way it is meant to be displayed.
+pub(crate) struct DecodedImage {
+    pub(crate) rgb: RgbImage,
+    /// The EXIF orientation applied to the pixels.
+    pub(crate) orientation: Orientation,
+}
+
+/// A face stored in the registry, in the order of the detected faces.
+pub(crate) struct StoredFace {
+    pub(crate) face_id: i64,
+    /// The person the face was recognized as, if any.
+    pub(crate) person_id: Option<i64>,
+}
+
 pub(crate) enum DetectResult {
     Skipped,
     NoFaces,
-    FacesDetected(Vec<i64>),
+    FacesDetected(DetectKind),
+}
+
+impl DetectResult {
+    pub(crate) fn from_stored_faces(faces: &[StoredFace]) -> Self {
+        let (known, unknown): (Vec<&StoredFace>, Vec<&StoredFace>) =
+            faces.iter().partition(|face| face.person_id.is_some());
+
+        let known_ids: Vec<i64> = known.iter().map(|face| face.face_id).collect();
+        let unknown_ids: Vec<i64> = unknown.iter().map(|face| face.face_id).collect();
+
+        match (known_ids.is_empty(), unknown_ids.is_empty()) {
+            (true, true) => DetectResult::NoFaces,
+            (false, true) => DetectResult::FacesDetected(DetectKind::AllFacesKnown(known_ids)),
+            (true, false) => DetectResult::FacesDetected(DetectKind::NoFacesKnown(unknown_ids)),
+            (false, false) => DetectResult::FacesDetected(DetectKind::SomeFacesKnown {
+                known_ids,
+                unknown_ids,
+            }),
+        }
+    }
 }
 
 impl FaceRecognizer {
@@ -55,68 +234,539 @@ impl FaceRecognizer {
         }
     }
 
-    pub async fn process_file(&self, input: &Path, options: FaceRecognizerOptions) -> DetectResult {
+    /// Creates a recognizer sharing the registry, but with its own copy of the models.
+    pub(crate) fn with_own_models(&self) -> Self {
+        Self::new(
+            Arc::new(self.models.as_ref().clone()),
+            self.person_registry.clone(),
+        )
+    }
+
+    pub async fn process_file(
+        &self,
+        input: &Path,
+        options: &FaceRecognizerOptions,
+    ) -> Result<DetectResult, RecognizeError> {
         info!("processing file {}", input.display());
-        let hash = Self::calc_hash(input);
+        let hash = Self::calc_hash(input)?;
 
-        let mut is_processed = false;
+        let processed_file_id = self.find_processed_file(&hash).await?;
+        if processed_file_id.is_some() && !options.skip_processed_check {
+            return Ok(DetectResult::Skipped);
+        }
 
-        let file_id = match self.person_registry.find_file(&hash).await {
-            Some((id, _path, _processed_at)) => {
-                // todo: update file path if different
-                info!("I already analyzed this file");
-                is_processed = true;
-                id
+        let image = Self::open_image(input)?;
+        let faces = self.detect_faces(&image.rgb, &options.detection, &options.encoding);
+
+        self.finish_file(hash, input, processed_file_id, image, &faces, options)
+            .await
+    }
+
+    /// Stores the detected faces and writes the annotated image if requested.
+    pub(crate) async fn finish_file(
+        &self,
+        hash: Hash,
+        input: &Path,
+        processed_file_id: Option<i64>,
+        image: DecodedImage,
+        faces: &[DetectedFace],
+        options: &FaceRecognizerOptions,
+    ) -> Result<DetectResult, RecognizeError> {
+        let Some(stored_faces) = self
+            .store_faces(
+                hash,
+                input,
+                processed_file_id,
+                image.orientation,
+                faces,
+                options,
+            )
+            .await?
+        else {
+            return Ok(DetectResult::Skipped);
+        };
+
+        if let Some(target) = &options.annotate {
+            self.write_annotated(input, image.rgb, faces, &stored_faces, target)
+                .await?;
+        }
+
+        Ok(DetectResult::from_stored_faces(&stored_faces))
+    }
+
+    /// Writes a copy of the image with faces outlined and labeled with names or face ids.
+    pub(crate) async fn write_annotated(
+        &self,
+        input: &Path,
+        mut image: RgbImage,
+        faces: &[DetectedFace],
+        stored_faces: &[StoredFace],
+        target: &AnnotateTarget,
+    ) -> Result<(), RecognizeError> {
+        let mut labels = Vec::with_capacity(stored_faces.len());
+        for face in stored_faces {
+            let label = match face.person_id {
+                Some(_) => self.person_registry.find_person_name(face.face_id).await?,
+                None => None,
+            };
+            labels.push(label.unwrap_or_else(|| format!("#{}", face.face_id)));
+        }
+
+        let output = match target {
+            AnnotateTarget::NextToInput => get_output_path(input),
+            AnnotateTarget::Directory { output, root } => {
+                let relative = input
+                    .strip_prefix(root)
+                    .unwrap_or(Path::new(input.file_name().unwrap_or_default()));
+                output.join(relative)
             }
-            None => {
-                self.person_registry
-                    .add_file(ProcessedFileInsert::new(hash, input))
-                    .await
+        };
+
+        let rects: Vec<Rectangle> = faces.iter().map(|face| face.rect).collect();
+        let landmarks: Vec<Vec<Point>> = faces.iter().map(|face| face.landmarks.clone()).collect();
+
+        // drawing and encoding large images would stall the other tasks
+        task::spawn_blocking(move || {
+            for ((rect, landmarks), label) in rects.iter().zip(landmarks.iter()).zip(labels.iter())
+            {
+                annotate_face(&mut image, rect, landmarks, label, ANNOTATION_COLOUR);
             }
+
+            if let Some(dir) = output.parent() {
+                fs::create_dir_all(dir).map_err(|err| RecognizeError::io(dir, err))?;
+            }
+            image
+                .save(&output)
+                .map_err(|err| RecognizeError::image(&output, err))?;
+
+            info!("annotated image saved to {}", output.display());
+            Ok(())
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Returns the id of the file if it has already been analysed.
+    pub(crate) async fn find_processed_file(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<i64>, RecognizeError> {
+        let Some((id, _path, _processed_at)) = self.person_registry.find_file(hash).await? else {
+            return Ok(None);
         };
 
-        if is_processed && !options.skip_processed_check {
-            return DetectResult::Skipped;
-        }
+        // todo: update file path if different
+        info!("I already analyzed this file");
+        Ok(Some(id))
+    }
 
-        let mut image = open(input).unwrap().to_rgb8();
-        let face_ids = self.detect(&mut image, file_id).await;
+    /// Finds stored faces similar to each face in the image, without persisting anything.
+    ///
+    /// Returns one entry per face detected in the query image, each with up to `k` matches,
+    /// or up to [`MAX_KNN_K`] matches within `max_distance` when `k` is `None`. Stored faces
+    /// of lower quality than `min_quality` are left out.
+    pub async fn search(
+        &self,
+        input: &Path,
+        k: Option<u32>,
+        max_distance: f32,
+        min_quality: Option<f64>,
+        detection: &DetectionOptions,
+    ) -> Result<Vec<QueryFaceMatches>, RecognizeError> {
+        let image = Self::open_image(input)?;
+        let faces = self.detect_faces(&image.rgb, detection, &EncodingOptions::default());
+
+        let mut results = Vec::with_capacity(faces.len());
+        for face in &faces {
+            let matches = self
+                .person_registry
+                .find_nearest(
+                    NearestQuery::Encoding(&face.encoding),
+                    k.unwrap_or(MAX_KNN_K),
+                    max_distance,
+                    min_quality,
+                )
+                .await?;
+
+            if k.is_none() && matches.len() as u32 == MAX_KNN_K {
+                warn!(
+                    "more than {} faces match, only the nearest ones are listed",
+                    MAX_KNN_K
+                );
+            }
 
-        if face_ids.is_empty() {
-            return DetectResult::NoFaces;
+            results.push(QueryFaceMatches {
+                rect: face.rect,
+                matches,
+            });
         }
 
-        DetectResult::FacesDetected(face_ids)
+        Ok(results)
     }
 
+    /// Runs the models on the image; nothing is stored.
+    ///
+    /// Locations and landmarks of the faces are in the coordinates of the image.
     #[instrument(skip(self, image), name = "detecting faces")]
-    async fn detect(&self, image: &RgbImage, file_id: i64) -> Vec<i64> {
+    pub(crate) fn detect_faces(
+        &self,
+        image: &RgbImage,
+        options: &DetectionOptions,
+        encoding_options: &EncodingOptions,
+    ) -> Vec<DetectedFace> {
         let start = Instant::now();
+
         let matrix = ImageMatrix::from_image(image);
+        let faces = self.locate_faces(image, &matrix, options);
+        let faces = self
+            .encode_faces(vec![(&matrix, faces)], encoding_options)
+            .pop()
+            .unwrap();
 
-        let face_locations = self.find_face_locations(&matrix);
-        let faces_landmarks = self.find_landmarks(&matrix, &face_locations);
-        let encodings = self.calculate_face_encodings(&matrix, faces_landmarks.as_slice());
+        info!("finished {:?}", start.elapsed());
 
-        let mut face_ids = Vec::with_capacity(face_locations.len());
-        for (location_rect, encoding) in face_locations.iter().zip(encodings.iter()) {
-            let face_id = self
-                .person_registry
-                .add_face(Some(file_id), encoding, location_rect)
-                .await;
+        faces
+    }
+
+    /// Finds faces and their landmarks in the image, without encoding them.
+    ///
+    /// `matrix` is the same image converted for dlib.
+    pub(crate) fn locate_faces(
+        &self,
+        image: &RgbImage,
+        matrix: &ImageMatrix,
+        options: &DetectionOptions,
+    ) -> Vec<LocatedFace> {
+        let mut faces = self.detect_upright(image, matrix, options);
+        if faces.is_empty() && options.retry_rotations {
+            faces = self.detect_rotated(image, options);
+        }
 
-            face_ids.push(face_id);
+        for face in &mut faces {
+            face.pose = estimate_head_pose(&face.landmarks, image.width(), image.height());
+            face.quality = face_quality(matrix, face);
         }
 
-        info!("finished {:?}", start.elapsed());
-        face_ids
+        faces
+    }
+
+    /// Encodes the faces located in each of the images.
+    ///
+    /// Faces of all the images go through the network in one batch, which pays off for
+    /// photos with only a face or two. The images are the matrices the faces were located in.
+    /// Returns the encoded faces of each image in order.
+    pub(crate) fn encode_faces(
+        &self,
+        images: Vec<(&ImageMatrix, Vec<LocatedFace>)>,
+        options: &EncodingOptions,
+    ) -> Vec<Vec<DetectedFace>> {
+        let images_with_faces: Vec<(&ImageMatrix, Vec<FaceLandmarks>)> = images
+            .iter()
+            .filter(|(_, faces)| !faces.is_empty())
+            .map(|(matrix, faces)| {
+                let landmarks = faces
+                    .iter()
+                    .map(|face| FaceLandmarks::new(&face.rect, &face.landmarks))
+                    .collect();
+                (*matrix, landmarks)
+            })
+            .collect();
+        let batch: Vec<(&ImageMatrix, &[FaceLandmarks])> = images_with_faces
+            .iter()
+            .map(|(matrix, landmarks)| (*matrix, landmarks.as_slice()))
+            .collect();
+
+        let encodings = self.calculate_face_encodings(&batch, options);
+        let mut encodings = encodings.iter();
+
+        images
+            .into_iter()
+            .map(|(_, faces)| {
+                faces
+                    .into_iter()
+                    .map(|face| {
+                        let encoding = encodings.next().expect("every face is encoded").clone();
+                        face.encoded(encoding, options.num_jitters)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Finds faces in the image as it is, `matrix` being the image converted for dlib.
+    fn detect_upright(
+        &self,
+        image: &RgbImage,
+        matrix: &ImageMatrix,
+        options: &DetectionOptions,
+    ) -> Vec<LocatedFace> {
+        let downscaled = options
+            .max_dimension
+            .filter(|max_dimension| image.width().max(image.height()) > *max_dimension)
+            .map(|max_dimension| downscale(image, max_dimension));
+
+        // the detectors are the slowest and hungriest for memory, so only they get the smaller
+        // image, landmarks are more accurate on the original one
+        let (detections, detector, scale) = match &downscaled {
+            Some(small) => {
+                info!(
+                    "detecting faces in the image downscaled to {}x{}",
+                    small.width(),
+                    small.height()
+                );
+                let small_matrix = ImageMatrix::from_image(small);
+                let (detections, detector) = self.find_face_locations(&small_matrix, options);
+                let scale = (
+                    image.width() as f64 / small.width() as f64,
+                    image.height() as f64 / small.height() as f64,
+                );
+                (detections, detector, Some(scale))
+            }
+            None => {
+                let (detections, detector) = self.find_face_locations(matrix, options);
+                (detections, detector, None)
+            }
+        };
+
+        let min_confidence = options.min_confidence.unwrap_or(f64::NEG_INFINITY);
+        let (locations, confidences): (Vec<Rectangle>, Vec<f64>) = detections
+            .iter()
+            .filter(|detection| detection.confidence >= min_confidence)
+            .map(|detection| match scale {
+                Some((scale_x, scale_y)) => (
+                    scale_rectangle(&detection.rect, scale_x, scale_y),
+                    detection.confidence,
+                ),
+                None => (detection.rect, detection.confidence),
+            })
+            .unzip();
+        if locations.len() < detections.len() {
+            info!(
+                "dropped {} faces below the minimum confidence",
+                detections.len() - locations.len()
+            );
+        }
+
+        let faces_landmarks = self.find_landmarks(matrix, &locations);
+
+        locations
+            .into_iter()
+            .zip(confidences)
+            .zip(faces_landmarks.iter())
+            .map(|((rect, confidence), landmarks)| LocatedFace {
+                rect,
+                confidence,
+                detector,
+                rotation: Rotation::None,
+                landmarks: landmarks.to_vec(),
+                pose: None,
+                quality: 0.0,
+            })
+            .collect()
+    }
+
+    /// Finds faces in the image turned by each of the right angles, e.g. for sideways scans.
+    ///
+    /// Faces found in more than one rotation are kept once, with the most confident detection.
+    fn detect_rotated(&self, image: &RgbImage, options: &DetectionOptions) -> Vec<LocatedFace> {
+        let (width, height) = image.dimensions();
+
+        let mut faces = Vec::new();
+        for rotation in Rotation::RETRIED {
+            info!(
+                "looking for faces in the image rotated {}°",
+                rotation.degrees()
+            );
+            let rotated = rotation.apply(image);
+            let rotated_matrix = ImageMatrix::from_image(&rotated);
+
+            for mut face in self.detect_upright(&rotated, &rotated_matrix, options) {
+                face.rect = rotation.rect_to_original(&face.rect, width, height);
+                face.landmarks = face
+                    .landmarks
+                    .iter()
+                    .map(|point| rotation.point_to_original(point, width, height))
+                    .collect();
+                face.rotation = rotation;
+                faces.push(face);
+            }
+        }
+
+        faces.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+        let mut unique_faces: Vec<LocatedFace> = Vec::with_capacity(faces.len());
+        for face in faces {
+            let duplicate = unique_faces
+                .iter()
+                .any(|unique| overlap(&unique.rect, &face.rect) > DUPLICATE_FACE_OVERLAP);
+            if !duplicate {
+                unique_faces.push(face);
+            }
+        }
+
+        unique_faces
+    }
+
+    /// Stores the file and its faces, assigning identities to faces similar to labeled ones.
+    ///
+    /// `processed_file_id` is the id of the file when it has been analysed before. Returns
+    /// `None` without storing anything when another copy of the file has been stored since it
+    /// was looked up, unless processed files are not skipped.
+    pub(crate) async fn store_faces(
+        &self,
+        hash: Hash,
+        input: &Path,
+        processed_file_id: Option<i64>,
+        orientation: Orientation,
+        faces: &[DetectedFace],
+        options: &FaceRecognizerOptions,
+    ) -> Result<Option<Vec<StoredFace>>, RecognizeError> {
+        // the same file may have been stored under another path since it was first looked up
+        let existing_file_id = match processed_file_id {
+            Some(id) => Some(id),
+            None => {
+                let stored_copy = self.person_registry.find_file(&hash).await?;
+                if stored_copy.is_some() && !options.skip_processed_check {
+                    info!("a copy of this file has just been analysed");
+                    return Ok(None);
+                }
+                stored_copy.map(|(id, _path, _processed_at)| id)
+            }
+        };
+        let file_id = match existing_file_id {
+            Some(id) => {
+                // files analysed before orientation was stored have none yet
+                self.person_registry
+                    .set_file_orientation(id, orientation.to_exif())
+                    .await?;
+                id
+            }
+            None => {
+                self.person_registry
+                    .add_file(ProcessedFileInsert::new(
+                        hash,
+                        input,
+                        orientation.to_exif(),
+                    )?)
+                    .await?
+            }
+        };
+
+        let face_inserts: Vec<FaceInsert> = faces
+            .iter()
+            .map(|face| FaceInsert {
+                location: face.rect,
+                encoding: &face.encoding,
+                jitters: face.num_jitters,
+                detector: face.detector.as_str(),
+                confidence: face.confidence,
+                rotation: face.rotation.degrees(),
+                landmarks: &face.landmarks,
+                pose: face.pose,
+                quality: face.quality,
+            })
+            .collect();
+
+        let face_ids = self
+            .person_registry
+            .add_faces(Some(file_id), &face_inserts)
+            .await?;
+
+        let mut stored_faces = Vec::with_capacity(face_ids.len());
+        for (face_id, face) in face_ids.into_iter().zip(faces.iter()) {
+            // encodings of faces seen from the side are unreliable
+            let turned_away = match (face.pose, options.match_max_angle) {
+                (Some(pose), Some(max_angle)) => !pose.is_within(max_angle),
+                _ => false,
+            };
+            // as are those of small, blurry or dark ones
+            let low_quality = options
+                .match_min_quality
+                .is_some_and(|min_quality| face.quality < min_quality);
+            let person_id = if turned_away {
+                info!("face {} is turned too far to be matched", face_id);
+                None
+            } else if low_quality {
+                info!(
+                    "face {} is of too low quality ({:.2}) to be matched",
+                    face_id, face.quality
+                );
+                None
+            } else {
+                self.match_person(&face.encoding, options).await?
+            };
+            if let Some(person_id) = person_id {
+                self.person_registry
+                    .assign_person(face_id, person_id)
+                    .await?;
+            }
+
+            stored_faces.push(StoredFace { face_id, person_id });
+        }
+
+        Ok(Some(stored_faces))
+    }
+
+    /// Picks the person most common among the nearest labeled faces.
+    ///
+    /// The person is returned only if they hold the majority of the votes.
+    async fn match_person(
+        &self,
+        encoding: &FaceEncoding,
+        options: &FaceRecognizerOptions,
+    ) -> Result<Option<i64>, RecognizeError> {
+        let neighbours = self
+            .person_registry
+            .find_nearest_labeled(encoding, options.match_neighbours)
+            .await?;
+
+        let mut votes: HashMap<i64, usize> = HashMap::new();
+        for (person_id, _distance) in neighbours
+            .iter()
+            .filter(|(_, distance)| *distance <= options.match_max_distance)
+        {
+            *votes.entry(*person_id).or_default() += 1;
+        }
+
+        let total_votes: usize = votes.values().sum();
+        let person_id = votes
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .filter(|(_, count)| *count * 2 > total_votes)
+            .map(|(person_id, _)| person_id);
+
+        Ok(person_id)
     }
 
-    fn calc_hash(input: &Path) -> Hash {
-        let file = File::open(input).unwrap();
-        let mmap = unsafe { Mmap::map(&file).unwrap() };
+    pub(crate) fn calc_hash(input: &Path) -> Result<Hash, RecognizeError> {
+        let file = File::open(input).map_err(|err| RecognizeError::io(input, err))?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|err| RecognizeError::io(input, err))?;
 
-        blake3::hash(&mmap)
+        Ok(blake3::hash(&mmap))
+    }
+
+    /// Decodes the image and rotates or flips it according to its EXIF orientation.
+    pub(crate) fn open_image(input: &Path) -> Result<DecodedImage, RecognizeError> {
+        let mut decoder = ImageReader::open(input)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|err| RecognizeError::io(input, err))?
+            .into_decoder()
+            .map_err(|err| RecognizeError::image(input, err))?;
+
+        // a broken orientation tag should not make the whole image unusable
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+
+        let mut image =
+            DynamicImage::from_decoder(decoder).map_err(|err| RecognizeError::image(input, err))?;
+        if orientation != Orientation::NoTransforms {
+            info!("applying EXIF orientation {:?}", orientation);
+            image.apply_orientation(orientation);
+        }
+
+        Ok(DecodedImage {
+            rgb: image.to_rgb8(),
+            orientation,
+        })
     }
 
     fn find_landmarks(&self, matrix: &ImageMatrix, rectangles: &[Rectangle]) -> Vec<FaceLandmarks> {
@@ -127,7 +777,7 @@ impl FaceRecognizer {
             .map(|face_location_rect| {
                 self.models
                     .landmarks_predictor
-                    .face_landmarks(&matrix, &face_location_rect)
+                    .face_landmarks(matrix, face_location_rect)
             })
             .collect();
 
@@ -141,9 +791,38 @@ impl FaceRecognizer {
         all_landmarks
     }
 
-    fn find_face_locations(&self, matrix: &ImageMatrix) -> FaceLocations {
+    /// Runs the detectors of the detector mode; the faces are not yet filtered by confidence.
+    fn find_face_locations(
+        &self,
+        matrix: &ImageMatrix,
+        options: &DetectionOptions,
+    ) -> (FaceDetections, Detector) {
         let face_locations_start = Instant::now();
-        let face_locations = self.models.face_detector.face_locations(&matrix);
+        let detect_with = |detector| {
+            (
+                self.detect_with(matrix, detector, options.upsample_times),
+                detector,
+            )
+        };
+
+        let (face_locations, detector) = match self.models.detector_mode {
+            DetectorMode::Hog => detect_with(Detector::Hog),
+            DetectorMode::Cnn => detect_with(Detector::Cnn),
+            DetectorMode::HogThenCnn => {
+                let (face_locations, detector) = detect_with(Detector::Hog);
+                // faces dropped for their confidence later on count as not found
+                let min_confidence = options.min_confidence.unwrap_or(f64::NEG_INFINITY);
+                if !face_locations
+                    .iter()
+                    .any(|detection| detection.confidence >= min_confidence)
+                {
+                    info!("HOG detector found no faces, trying the CNN one");
+                    detect_with(Detector::Cnn)
+                } else {
+                    (face_locations, detector)
+                }
+            }
+        };
 
         HISTOGRAM_F_D.record(
             face_locations_start.elapsed().as_millis() as u64,
@@ -151,24 +830,46 @@ impl FaceRecognizer {
         );
 
         info!(
-            "found {:?} faces in {:?}",
+            "found {:?} faces with {} detector in {:?}",
             face_locations.len(),
+            detector.as_str(),
             face_locations_start.elapsed()
         );
 
-        face_locations
+        (face_locations, detector)
     }
 
-    fn calculate_face_encodings(
+    fn detect_with(
         &self,
         matrix: &ImageMatrix,
-        landmarks: &[FaceLandmarks],
+        detector: Detector,
+        upsample_times: u32,
+    ) -> FaceDetections {
+        match detector {
+            Detector::Hog => self
+                .models
+                .hog_detector
+                .face_detections(matrix, upsample_times),
+            Detector::Cnn => self
+                .models
+                .cnn_detector
+                .as_ref()
+                .expect("CNN detector is loaded for the modes using it")
+                .face_detections(matrix, upsample_times),
+        }
+    }
+
+    fn calculate_face_encodings(
+        &self,
+        faces: &[(&ImageMatrix, &[FaceLandmarks])],
+        options: &EncodingOptions,
     ) -> FaceEncodings {
         let face_encoding_start = Instant::now();
-        let encodings = self
-            .models
-            .face_encoding
-            .get_face_encodings(&matrix, landmarks, 0);
+        let encodings = self.models.face_encoding.get_face_encodings_batch(
+            faces,
+            options.num_jitters,
+            options.batch_size,
+        );
 
         HISTOGRAM_F_E.record(
             face_encoding_start.elapsed().as_millis() as u64,
@@ -183,3 +884,12 @@ impl FaceRecognizer {
         encodings
     }
 }
+
+/// Resizes the image to fit within `max_dimension`, keeping its aspect ratio.
+fn downscale(image: &RgbImage, max_dimension: u32) -> RgbImage {
+    let scale = max_dimension as f64 / image.width().max(image.height()) as f64;
+    let width = ((image.width() as f64 * scale).round() as u32).max(1);
+    let height = ((image.height() as f64 * scale).round() as u32).max(1);
+
+    resize(image, width, height, FilterType::Triangle)
+}
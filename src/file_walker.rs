@@ -0,0 +1,186 @@
+//! Selecting the image files to analyse when recognizing a whole directory.
+
+use crate::error::RecognizeError;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use image::ImageFormat;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::info;
+use walkdir::{DirEntry, WalkDir};
+
+/// Number of leading bytes needed to recognize any of the supported formats.
+const MAGIC_BYTES_LEN: usize = 32;
+
+#[derive(Debug)]
+pub struct WalkOptions {
+    /// When set, only files matching one of these globs are analysed.
+    pub(crate) include: Option<GlobSet>,
+    pub(crate) exclude: Option<GlobSet>,
+    /// Depth of the walk, where files directly in the root directory are at depth 1.
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) follow_symlinks: bool,
+    /// Whether to walk into files and directories whose name starts with a dot.
+    pub(crate) hidden: bool,
+    /// Whether to skip copies written by `--annotate` next to their originals.
+    pub(crate) skip_annotated_copies: bool,
+    /// A directory not to walk into, e.g. the one `--annotate` writes copies to.
+    pub(crate) excluded_dir: Option<PathBuf>,
+}
+
+/// Builds a glob set from the patterns, or `None` when there are no patterns.
+pub fn build_glob_set<'a>(
+    patterns: impl IntoIterator<Item = &'a String>,
+) -> Result<Option<GlobSet>, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    let mut is_empty = true;
+
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+        is_empty = false;
+    }
+
+    if is_empty {
+        return Ok(None);
+    }
+
+    builder.build().map(Some)
+}
+
+/// Lists image files in the directory; globs are matched against paths relative to `root`.
+///
+/// Also returns the files and directories which could not be read, e.g. for lack of
+/// permissions or because they are broken symlinks.
+pub fn collect_images(
+    root: &Path,
+    options: &WalkOptions,
+) -> (Vec<PathBuf>, Vec<(PathBuf, RecognizeError)>) {
+    let mut walker = WalkDir::new(root).follow_links(options.follow_symlinks);
+    if let Some(max_depth) = options.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    let mut skipped = 0;
+    let mut annotated = 0;
+    let mut images = Vec::new();
+    let mut failures = Vec::new();
+
+    let excluded_dir = options
+        .excluded_dir
+        .as_deref()
+        .and_then(|dir| walked_path(root, dir));
+
+    for entry in walker.into_iter().filter_entry(|e| {
+        e.depth() == 0
+            || ((options.hidden || !is_hidden(e)) && Some(e.path()) != excluded_dir.as_deref())
+    }) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let path = err.path().unwrap_or(root).to_path_buf();
+                let err = RecognizeError::io(&path, err.into());
+                failures.push((path, err));
+                continue;
+            }
+        };
+
+        // symlinks to files are analysed even when links to directories are not followed
+        if !entry.file_type().is_file() {
+            if !entry.path_is_symlink() {
+                continue;
+            }
+            match fs::metadata(entry.path()) {
+                Ok(metadata) if metadata.is_file() => {}
+                Ok(_) => continue,
+                Err(err) => {
+                    let err = RecognizeError::io(entry.path(), err);
+                    failures.push((entry.into_path(), err));
+                    continue;
+                }
+            }
+        }
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+
+        if let Some(include) = &options.include
+            && !include.is_match(relative)
+        {
+            continue;
+        }
+
+        if let Some(exclude) = &options.exclude
+            && exclude.is_match(relative)
+        {
+            continue;
+        }
+
+        if options.skip_annotated_copies && is_annotated_copy(entry.path()) {
+            annotated += 1;
+        } else if is_image(entry.path()) {
+            images.push(entry.into_path());
+        } else {
+            skipped += 1;
+        }
+    }
+
+    if skipped > 0 {
+        info!("skipped {} files which are not images", skipped);
+    }
+    if annotated > 0 {
+        info!("skipped {} annotated copies of images", annotated);
+    }
+
+    (images, failures)
+}
+
+/// The path under which the walk of `root` meets `dir`, or `None` when `dir` is outside of it.
+fn walked_path(root: &Path, dir: &Path) -> Option<PathBuf> {
+    let canonical_root = fs::canonicalize(root).ok()?;
+    let canonical_dir = fs::canonicalize(dir).ok()?;
+    let relative = canonical_dir.strip_prefix(canonical_root).ok()?;
+
+    Some(root.join(relative))
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+/// Whether the file is a copy written by `--annotate` next to its original, e.g.
+/// `photo_new.jpg` next to `photo.jpg`.
+fn is_annotated_copy(path: &Path) -> bool {
+    let Some(original_stem) = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.strip_suffix("_new"))
+    else {
+        return false;
+    };
+
+    // copies of images without an extension are saved as png
+    let original = match path.extension() {
+        Some(ext) => format!("{}.{}", original_stem, ext.to_string_lossy()),
+        None => original_stem.to_string(),
+    };
+
+    path.with_file_name(&original).is_file() || path.with_file_name(original_stem).is_file()
+}
+
+/// Recognizes images by their extension, falling back to the magic bytes for unknown ones.
+fn is_image(path: &Path) -> bool {
+    if let Ok(format) = ImageFormat::from_path(path) {
+        return format.reading_enabled();
+    }
+
+    let mut magic = [0u8; MAGIC_BYTES_LEN];
+    let len = match File::open(path).and_then(|mut file| file.read(&mut magic)) {
+        Ok(len) => len,
+        Err(_) => return false,
+    };
+
+    image::guess_format(&magic[..len]).is_ok_and(|format| format.reading_enabled())
+}
@@ -0,0 +1,371 @@
+//! Head pose estimation from 68 point landmarks.
+//!
+//! Six landmarks are matched with a generic 3D face model and the pose is solved with POSIT
+//! (DeMenthon & Davis), which needs neither a calibrated camera nor an initial guess.
+
+use dlib_wrappers::Point;
+use dlib_wrappers::landmark_prediction::Landmarks68;
+
+/// Points of a generic face in millimetres-like units, with x to the right of the image,
+/// y down and z away from the camera, in the order of [`model_landmarks`].
+const FACE_MODEL: [[f64; 3]; 6] = [
+    // nose tip, the reference point
+    [0.0, 0.0, 0.0],
+    // chin
+    [0.0, 330.0, 65.0],
+    // outer corner of the left eye
+    [-225.0, -170.0, 135.0],
+    // outer corner of the right eye
+    [225.0, -170.0, 135.0],
+    // left corner of the mouth
+    [-150.0, 150.0, 125.0],
+    // right corner of the mouth
+    [150.0, 150.0, 125.0],
+];
+
+const MAX_ITERATIONS: usize = 100;
+
+/// Orientation of a head in degrees, all zero for a face looking straight into the camera.
+///
+/// Positive yaw turns the face towards the left of the image, positive pitch tilts it down
+/// and positive roll rotates it clockwise.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct HeadPose {
+    pub(crate) yaw: f64,
+    pub(crate) pitch: f64,
+    pub(crate) roll: f64,
+}
+
+impl HeadPose {
+    /// Whether the face is turned no more than `max_angle` degrees left, right, up or down.
+    pub(crate) fn is_within(&self, max_angle: f64) -> bool {
+        self.yaw.abs() <= max_angle && self.pitch.abs() <= max_angle
+    }
+}
+
+/// Estimates the pose of the head from landmarks in the coordinates of a `width` x `height` image.
+///
+/// The camera is assumed to have a focal length equal to the image width and its centre in
+/// the middle of the image, which is close enough for typical photos.
+/// Returns `None` for landmarks of models other than the 68 point one.
+pub(crate) fn estimate_head_pose(landmarks: &[Point], width: u32, height: u32) -> Option<HeadPose> {
+    let landmarks = Landmarks68::new(landmarks).ok()?;
+
+    let focal_length = width as f64;
+    let (centre_x, centre_y) = (width as f64 / 2.0, height as f64 / 2.0);
+    let image_points = model_landmarks(&landmarks)
+        .map(|point| (point.x as f64 - centre_x, point.y as f64 - centre_y));
+
+    let rotation = posit(&image_points, focal_length)?;
+
+    Some(HeadPose {
+        yaw: (-rotation[2][0]).clamp(-1.0, 1.0).asin().to_degrees(),
+        pitch: rotation[2][1].atan2(rotation[2][2]).to_degrees(),
+        roll: rotation[1][0].atan2(rotation[0][0]).to_degrees(),
+    })
+}
+
+/// Landmarks matching the points of [`FACE_MODEL`].
+fn model_landmarks(landmarks: &Landmarks68) -> [Point; 6] {
+    [
+        landmarks.nose_bridge()[3],
+        landmarks.jawline()[8],
+        landmarks.left_eye()[0],
+        landmarks.right_eye()[3],
+        landmarks.outer_lips()[0],
+        landmarks.outer_lips()[6],
+    ]
+}
+
+/// Finds the rotation of [`FACE_MODEL`] projecting to the image points, which are relative
+/// to the centre of the image.
+fn posit(image_points: &[(f64, f64); 6], focal_length: f64) -> Option<[[f64; 3]; 3]> {
+    // vectors from the reference point to the other model points and their pseudo-inverse
+    let object: Vec<[f64; 3]> = FACE_MODEL[1..]
+        .iter()
+        .map(|point| sub(point, &FACE_MODEL[0]))
+        .collect();
+    let mut gram = [[0.0; 3]; 3];
+    for vector in &object {
+        for row in 0..3 {
+            for column in 0..3 {
+                gram[row][column] += vector[row] * vector[column];
+            }
+        }
+    }
+    let gram_inverse = invert(&gram)?;
+    let pseudo_inverse: Vec<[f64; 3]> = object
+        .iter()
+        .map(|vector| mul_vector(&gram_inverse, vector))
+        .collect();
+
+    let (reference_x, reference_y) = image_points[0];
+    let mut corrections = vec![0.0; object.len()];
+    let mut rotation = [[0.0; 3]; 3];
+
+    for _ in 0..MAX_ITERATIONS {
+        let mut i = [0.0; 3];
+        let mut j = [0.0; 3];
+        for (index, column) in pseudo_inverse.iter().enumerate() {
+            let (x, y) = image_points[index + 1];
+            let x = x * (1.0 + corrections[index]) - reference_x;
+            let y = y * (1.0 + corrections[index]) - reference_y;
+            for axis in 0..3 {
+                i[axis] += column[axis] * x;
+                j[axis] += column[axis] * y;
+            }
+        }
+
+        let (scale_i, scale_j) = (norm(&i), norm(&j));
+        if scale_i == 0.0 || scale_j == 0.0 {
+            return None;
+        }
+        let i = scale(&i, 1.0 / scale_i);
+        let j = scale(&j, 1.0 / scale_j);
+        let k = cross(&i, &j);
+        let k = scale(&k, 1.0 / norm(&k));
+        // i and j are only approximately perpendicular, so make the rotation orthonormal
+        let j = cross(&k, &i);
+        rotation = [i, j, k];
+
+        let distance = focal_length / ((scale_i + scale_j) / 2.0);
+        let mut change: f64 = 0.0;
+        for (correction, vector) in corrections.iter_mut().zip(object.iter()) {
+            let updated = dot(vector, &k) / distance;
+            change = change.max((updated - *correction).abs());
+            *correction = updated;
+        }
+
+        if change < 1e-9 {
+            break;
+        }
+    }
+
+    rotation
+        .iter()
+        .flatten()
+        .all(|value| value.is_finite())
+        .then_some(rotation)
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: &[f64; 3], factor: f64) -> [f64; 3] {
+    [a[0] * factor, a[1] * factor, a[2] * factor]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn norm(a: &[f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn mul_vector(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    [dot(&m[0], v), dot(&m[1], v), dot(&m[2], v)]
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *m;
+    let det = a * (e * i - f * h) - b * (d * i - f * g) + c * (d * h - e * g);
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+
+    Some([
+        [
+            (e * i - f * h) / det,
+            (c * h - b * i) / det,
+            (b * f - c * e) / det,
+        ],
+        [
+            (f * g - d * i) / det,
+            (a * i - c * g) / det,
+            (c * d - a * f) / det,
+        ],
+        [
+            (d * h - e * g) / det,
+            (b * g - a * h) / det,
+            (a * e - b * d) / det,
+        ],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 4000;
+    const HEIGHT: u32 = 3000;
+
+    /// Rotation turning the head by the angles, in the conventions of [`HeadPose`].
+    fn rotation(pose: &HeadPose) -> [[f64; 3]; 3] {
+        let (sin_yaw, cos_yaw) = pose.yaw.to_radians().sin_cos();
+        let (sin_pitch, cos_pitch) = pose.pitch.to_radians().sin_cos();
+        let (sin_roll, cos_roll) = pose.roll.to_radians().sin_cos();
+
+        let roll = [
+            [cos_roll, -sin_roll, 0.0],
+            [sin_roll, cos_roll, 0.0],
+            [0.0, 0.0, 1.0],
+        ];
+        let yaw = [
+            [cos_yaw, 0.0, sin_yaw],
+            [0.0, 1.0, 0.0],
+            [-sin_yaw, 0.0, cos_yaw],
+        ];
+        let pitch = [
+            [1.0, 0.0, 0.0],
+            [0.0, cos_pitch, -sin_pitch],
+            [0.0, sin_pitch, cos_pitch],
+        ];
+
+        mul(&mul(&roll, &yaw), &pitch)
+    }
+
+    fn mul(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+        let mut product = [[0.0; 3]; 3];
+        for (row, product_row) in product.iter_mut().enumerate() {
+            for (column, value) in product_row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| a[row][k] * b[k][column]).sum();
+            }
+        }
+        product
+    }
+
+    /// 68 landmarks of the face model seen by the camera of [`estimate_head_pose`], with the
+    /// nose tip in front of the camera a bit off the centre of the image.
+    fn project(pose: &HeadPose) -> Vec<Point> {
+        let rotation = rotation(pose);
+        let translation = [150.0, -100.0, 6000.0];
+        let focal_length = WIDTH as f64;
+
+        let projected: Vec<Point> = FACE_MODEL
+            .iter()
+            .map(|point| {
+                let camera = mul_vector(&rotation, point);
+                let (x, y, z) = (
+                    camera[0] + translation[0],
+                    camera[1] + translation[1],
+                    camera[2] + translation[2],
+                );
+                Point {
+                    x: (focal_length * x / z + WIDTH as f64 / 2.0).round() as i64,
+                    y: (focal_length * y / z + HEIGHT as f64 / 2.0).round() as i64,
+                }
+            })
+            .collect();
+
+        let mut landmarks = vec![projected[0]; 68];
+        for (index, point) in [30, 8, 36, 45, 48, 54].into_iter().zip(projected) {
+            landmarks[index] = point;
+        }
+        landmarks
+    }
+
+    fn assert_recovers(yaw: f64, pitch: f64, roll: f64) {
+        let expected = HeadPose { yaw, pitch, roll };
+
+        let pose = estimate_head_pose(&project(&expected), WIDTH, HEIGHT).unwrap();
+
+        for (name, actual, expected) in [
+            ("yaw", pose.yaw, yaw),
+            ("pitch", pose.pitch, pitch),
+            ("roll", pose.roll, roll),
+        ] {
+            assert!(
+                (actual - expected).abs() < 1.0,
+                "{} {:.2} estimated as {:.2} in {:?}",
+                name,
+                expected,
+                actual,
+                pose
+            );
+        }
+    }
+
+    #[test]
+    fn test_frontal_face() {
+        assert_recovers(0.0, 0.0, 0.0);
+    }
+
+    #[test]
+    fn test_single_angles() {
+        assert_recovers(30.0, 0.0, 0.0);
+        assert_recovers(-30.0, 0.0, 0.0);
+        assert_recovers(0.0, 20.0, 0.0);
+        assert_recovers(0.0, -20.0, 0.0);
+        assert_recovers(0.0, 0.0, 15.0);
+        assert_recovers(0.0, 0.0, -15.0);
+    }
+
+    #[test]
+    fn test_combined_angles() {
+        assert_recovers(25.0, 10.0, -5.0);
+        assert_recovers(-40.0, -15.0, 20.0);
+    }
+
+    #[test]
+    fn test_sign_conventions() {
+        let frontal = project(&HeadPose {
+            yaw: 0.0,
+            pitch: 0.0,
+            roll: 0.0,
+        });
+        let turned = project(&HeadPose {
+            yaw: 30.0,
+            pitch: 0.0,
+            roll: 0.0,
+        });
+        let tilted = project(&HeadPose {
+            yaw: 0.0,
+            pitch: 20.0,
+            roll: 0.0,
+        });
+        let rotated = project(&HeadPose {
+            yaw: 0.0,
+            pitch: 0.0,
+            roll: 15.0,
+        });
+
+        // the chin sticks out less than the nose tip, so it lags behind when the head turns
+        let offset = |landmarks: &[Point]| landmarks[30].x - landmarks[8].x;
+        // positive yaw moves the nose tip towards the left of the image
+        assert!(offset(&turned) < offset(&frontal));
+        // positive pitch moves the nose tip down, away from the eyes
+        let eyes_above_nose = |landmarks: &[Point]| landmarks[30].y - landmarks[36].y;
+        assert!(eyes_above_nose(&tilted) > eyes_above_nose(&frontal));
+        // positive roll moves the outer corner of the right eye below that of the left one
+        assert!(rotated[45].y > rotated[36].y);
+    }
+
+    #[test]
+    fn test_other_models_have_no_pose() {
+        let landmarks = vec![Point { x: 1, y: 1 }; 5];
+
+        assert_eq!(estimate_head_pose(&landmarks, WIDTH, HEIGHT), None);
+    }
+
+    #[test]
+    fn test_is_within() {
+        let pose = HeadPose {
+            yaw: -20.0,
+            pitch: 10.0,
+            roll: 45.0,
+        };
+
+        assert!(pose.is_within(20.0));
+        assert!(!pose.is_within(15.0));
+    }
+}
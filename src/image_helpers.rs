@@ -1,18 +1,426 @@
+use deunicode::deunicode;
 use dlib_wrappers::{Point, Rectangle};
+use image::imageops::{rotate90, rotate180, rotate270};
 use image::{Rgb, RgbImage};
 
+const GLYPH_WIDTH: i64 = 5;
+const GLYPH_HEIGHT: i64 = 7;
+
+/// Draws the pixel, ignoring coordinates outside the image.
+fn put_pixel_clipped(image: &mut RgbImage, x: i64, y: i64, colour: Rgb<u8>) {
+    if x >= 0 && y >= 0 && x < image.width() as i64 && y < image.height() as i64 {
+        image.put_pixel(x as u32, y as u32, colour);
+    }
+}
+
+fn fill_rectangle(
+    image: &mut RgbImage,
+    left: i64,
+    top: i64,
+    right: i64,
+    bottom: i64,
+    colour: Rgb<u8>,
+) {
+    for y in top..bottom {
+        for x in left..right {
+            put_pixel_clipped(image, x, y, colour);
+        }
+    }
+}
+
 pub fn draw_rectangle(image: &mut RgbImage, rect: &Rectangle, colour: Rgb<u8>) {
-    for x in rect.left..rect.right {
-        image.put_pixel(x as u32, rect.top as u32, colour);
-        image.put_pixel(x as u32, rect.bottom as u32, colour);
+    // detectors may return rectangles reaching outside the image, i.e. with negative coordinates
+    let (left, top) = (rect.left as i64, rect.top as i64);
+    let (right, bottom) = (rect.right as i64, rect.bottom as i64);
+
+    for x in left..=right {
+        put_pixel_clipped(image, x, top, colour);
+        put_pixel_clipped(image, x, bottom, colour);
+    }
+
+    for y in top..=bottom {
+        put_pixel_clipped(image, left, y, colour);
+        put_pixel_clipped(image, right, y, colour);
     }
+}
+
+/// Scales the rectangle's coordinates, e.g. to map it from a resized image to the original one.
+pub fn scale_rectangle(rect: &Rectangle, scale_x: f64, scale_y: f64) -> Rectangle {
+    // coordinates of rectangles reaching outside the image are negative numbers cast to u64
+    let scale = |value: u64, scale: f64| ((value as i64) as f64 * scale).round() as i64 as u64;
+
+    Rectangle {
+        left: scale(rect.left, scale_x),
+        top: scale(rect.top, scale_y),
+        right: scale(rect.right, scale_x),
+        bottom: scale(rect.bottom, scale_y),
+    }
+}
 
-    for y in rect.top..rect.bottom {
-        image.put_pixel(rect.left as u32, y as u32, colour);
-        image.put_pixel(rect.right as u32, y as u32, colour);
+/// Intersection over union of the rectangles, 0 when they are disjoint and 1 when equal.
+pub fn overlap(a: &Rectangle, b: &Rectangle) -> f64 {
+    let (a_left, a_top, a_right, a_bottom) = signed(a);
+    let (b_left, b_top, b_right, b_bottom) = signed(b);
+
+    let width = (a_right.min(b_right) - a_left.max(b_left)).max(0);
+    let height = (a_bottom.min(b_bottom) - a_top.max(b_top)).max(0);
+    let intersection = (width * height) as f64;
+
+    let area =
+        |left: i64, top: i64, right: i64, bottom: i64| ((right - left) * (bottom - top)) as f64;
+    let union = area(a_left, a_top, a_right, a_bottom) + area(b_left, b_top, b_right, b_bottom)
+        - intersection;
+
+    if union <= 0.0 {
+        0.0
+    } else {
+        intersection / union
+    }
+}
+
+fn signed(rect: &Rectangle) -> (i64, i64, i64, i64) {
+    (
+        rect.left as i64,
+        rect.top as i64,
+        rect.right as i64,
+        rect.bottom as i64,
+    )
+}
+
+/// Clockwise rotation of an image by a right angle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    /// Rotations tried when no faces are found in the image as it is.
+    pub const RETRIED: [Rotation; 3] = [
+        Rotation::Clockwise90,
+        Rotation::Clockwise180,
+        Rotation::Clockwise270,
+    ];
+
+    pub fn degrees(self) -> u32 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Clockwise90 => 90,
+            Rotation::Clockwise180 => 180,
+            Rotation::Clockwise270 => 270,
+        }
+    }
+
+    pub fn apply(self, image: &RgbImage) -> RgbImage {
+        match self {
+            Rotation::None => image.clone(),
+            Rotation::Clockwise90 => rotate90(image),
+            Rotation::Clockwise180 => rotate180(image),
+            Rotation::Clockwise270 => rotate270(image),
+        }
+    }
+
+    /// Maps a point of the rotated image to the `width` x `height` image it was rotated from.
+    pub fn point_to_original(self, point: &Point, width: u32, height: u32) -> Point {
+        let (width, height) = (width as i64, height as i64);
+
+        match self {
+            Rotation::None => *point,
+            Rotation::Clockwise90 => Point {
+                x: point.y,
+                y: height - 1 - point.x,
+            },
+            Rotation::Clockwise180 => Point {
+                x: width - 1 - point.x,
+                y: height - 1 - point.y,
+            },
+            Rotation::Clockwise270 => Point {
+                x: width - 1 - point.y,
+                y: point.x,
+            },
+        }
+    }
+
+    /// Maps a rectangle of the rotated image to the `width` x `height` image it was rotated from.
+    pub fn rect_to_original(self, rect: &Rectangle, width: u32, height: u32) -> Rectangle {
+        let (left, top, right, bottom) = signed(rect);
+        let a = self.point_to_original(&Point { x: left, y: top }, width, height);
+        let b = self.point_to_original(
+            &Point {
+                x: right,
+                y: bottom,
+            },
+            width,
+            height,
+        );
+
+        Rectangle {
+            left: a.x.min(b.x) as u64,
+            top: a.y.min(b.y) as u64,
+            right: a.x.max(b.x) as u64,
+            bottom: a.y.max(b.y) as u64,
+        }
     }
 }
 
 pub fn draw_point(image: &mut RgbImage, point: &Point, colour: Rgb<u8>) {
-    image.put_pixel(point.x as u32, point.y as u32, colour);
+    put_pixel_clipped(image, point.x, point.y, colour);
+}
+
+/// Draws the text with a built-in 5x7 font, each font pixel being a `scale` sized square.
+///
+/// Only digits, latin letters (drawn as capitals) and a few symbols are supported. Other
+/// characters are transliterated first, so that e.g. `Łódź` is drawn as `LODZ`, and those
+/// still missing are drawn as `?`. `(x, y)` is the top left corner of the text.
+pub fn draw_text(image: &mut RgbImage, text: &str, x: i64, y: i64, scale: i64, colour: Rgb<u8>) {
+    for (index, c) in deunicode(text).chars().enumerate() {
+        let glyph_x = x + index as i64 * (GLYPH_WIDTH + 1) * scale;
+        let rows = glyph(c);
+
+        for (row_index, row) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+
+                let pixel_x = glyph_x + column * scale;
+                let pixel_y = y + row_index as i64 * scale;
+                fill_rectangle(
+                    image,
+                    pixel_x,
+                    pixel_y,
+                    pixel_x + scale,
+                    pixel_y + scale,
+                    colour,
+                );
+            }
+        }
+    }
+}
+
+/// Size of the text drawn by [`draw_text`] as `(width, height)`.
+pub fn text_size(text: &str, scale: i64) -> (i64, i64) {
+    let len = deunicode(text).chars().count() as i64;
+
+    (
+        (len * (GLYPH_WIDTH + 1) - 1).max(0) * scale,
+        GLYPH_HEIGHT * scale,
+    )
+}
+
+/// Outlines the face, marks its landmarks and writes the label above it.
+pub fn annotate_face(
+    image: &mut RgbImage,
+    rect: &Rectangle,
+    landmarks: &[Point],
+    label: &str,
+    colour: Rgb<u8>,
+) {
+    // keep the annotations visible on high resolution photos
+    let thickness = (image.width().max(image.height()) / 800).max(1) as i64;
+
+    for offset in 0..thickness {
+        let rect = Rectangle {
+            left: (rect.left as i64 - offset) as u64,
+            top: (rect.top as i64 - offset) as u64,
+            right: (rect.right as i64 + offset) as u64,
+            bottom: (rect.bottom as i64 + offset) as u64,
+        };
+        draw_rectangle(image, &rect, colour);
+    }
+
+    for point in landmarks {
+        for dy in -thickness..=thickness {
+            for dx in -thickness..=thickness {
+                let point = Point {
+                    x: point.x + dx,
+                    y: point.y + dy,
+                };
+                draw_point(image, &point, colour);
+            }
+        }
+    }
+
+    let scale = thickness * 2;
+    let padding = scale;
+    let (text_width, text_height) = text_size(label, scale);
+
+    let left = rect.left as i64 - thickness + 1;
+    let mut top = rect.top as i64 - thickness - text_height - 2 * padding;
+    if top < 0 {
+        // no room above the face, so put the label inside the rectangle
+        top = rect.top as i64 + thickness;
+    }
+
+    fill_rectangle(
+        image,
+        left,
+        top,
+        left + text_width + 2 * padding,
+        top + text_height + 2 * padding,
+        Rgb([0, 0, 0]),
+    );
+    draw_text(image, label, left + padding, top + padding, scale, colour);
+}
+
+/// Rows of a 5x7 glyph, the highest of the 5 bits being the leftmost pixel.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ' ' => [0x00; 7],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 7;
+    const HEIGHT: u32 = 4;
+
+    const ALL_ROTATIONS: [Rotation; 4] = [
+        Rotation::None,
+        Rotation::Clockwise90,
+        Rotation::Clockwise180,
+        Rotation::Clockwise270,
+    ];
+
+    fn rect(left: i64, top: i64, right: i64, bottom: i64) -> Rectangle {
+        Rectangle {
+            left: left as u64,
+            top: top as u64,
+            right: right as u64,
+            bottom: bottom as u64,
+        }
+    }
+
+    /// Bounding box of the white pixels, with inclusive right and bottom edges like dlib's.
+    fn white_box(image: &RgbImage) -> Rectangle {
+        let white: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[0] == 255)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+
+        rect(
+            white.iter().map(|(x, _)| *x).min().unwrap() as i64,
+            white.iter().map(|(_, y)| *y).min().unwrap() as i64,
+            white.iter().map(|(x, _)| *x).max().unwrap() as i64,
+            white.iter().map(|(_, y)| *y).max().unwrap() as i64,
+        )
+    }
+
+    #[test]
+    fn test_point_to_original() {
+        for rotation in ALL_ROTATIONS {
+            for (x, y) in [(0, 0), (6, 0), (0, 3), (6, 3), (2, 1)] {
+                let mut image = RgbImage::new(WIDTH, HEIGHT);
+                image.put_pixel(x, y, Rgb([255, 255, 255]));
+
+                let rotated = white_box(&rotation.apply(&image));
+                let point = Point {
+                    x: rotated.left as i64,
+                    y: rotated.top as i64,
+                };
+
+                assert_eq!(
+                    rotation.point_to_original(&point, WIDTH, HEIGHT),
+                    Point {
+                        x: x as i64,
+                        y: y as i64
+                    },
+                    "{:?}",
+                    rotation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rect_to_original() {
+        let original = rect(1, 0, 4, 2);
+        let mut image = RgbImage::new(WIDTH, HEIGHT);
+        fill_rectangle(&mut image, 1, 0, 5, 3, Rgb([255, 255, 255]));
+
+        for rotation in ALL_ROTATIONS {
+            let rotated = white_box(&rotation.apply(&image));
+
+            assert_eq!(
+                rotation.rect_to_original(&rotated, WIDTH, HEIGHT),
+                original,
+                "{:?}",
+                rotation
+            );
+        }
+    }
+
+    #[test]
+    fn test_overlap() {
+        let a = rect(0, 0, 10, 10);
+
+        assert_eq!(overlap(&a, &a), 1.0);
+        assert_eq!(overlap(&a, &rect(20, 0, 30, 10)), 0.0);
+        // touching edges do not overlap
+        assert_eq!(overlap(&a, &rect(10, 0, 20, 10)), 0.0);
+        // half of each rectangle is shared, a third of their union
+        assert!((overlap(&a, &rect(5, 0, 15, 10)) - 1.0 / 3.0).abs() < 1e-9);
+        // a rectangle covering half of the other one
+        assert_eq!(overlap(&a, &rect(0, 0, 10, 5)), 0.5);
+        assert_eq!(overlap(&rect(0, 0, 0, 0), &rect(0, 0, 0, 0)), 0.0);
+    }
+
+    #[test]
+    fn test_scale_rectangle() {
+        assert_eq!(
+            scale_rectangle(&rect(10, 20, 30, 41), 2.0, 0.5),
+            rect(20, 10, 60, 21)
+        );
+        // rectangles reaching over the top left corner of the image keep their sign
+        assert_eq!(
+            scale_rectangle(&rect(-4, -3, 8, 9), 1.5, 2.0),
+            rect(-6, -6, 12, 18)
+        );
+    }
 }
@@ -1,28 +1,48 @@
 #![allow(dead_code)]
 
-use crate::face_recognizer::{DetectResult, FaceRecognizer, FaceRecognizerOptions};
-use crate::person_registry::person_registry_sqlite::PersonRegistrySqlite;
-use clap::{Arg, ArgAction};
+use crate::error::RecognizeError;
+use crate::face_clustering::{ClusterOptions, cluster_unlabeled_faces};
+use crate::face_crops::write_face_crops;
+use crate::face_recognizer::{
+    AnnotateTarget, DetectResult, DetectionOptions, EncodingOptions, FaceRecognizer,
+    FaceRecognizerOptions,
+};
+use crate::file_walker::{WalkOptions, build_glob_set, collect_images};
+use crate::models::{
+    DetectorMode, LandmarkPoints, ModelKind, ModelManager, ModelStatus, required_models,
+};
+use crate::person_registry::person_registry_sqlite::{
+    MAX_KNN_K, NearestQuery, PersonRegistrySqlite,
+};
+use clap::builder::{PossibleValuesParser, RangedU64ValueParser, TypedValueParser};
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches};
 use directories::ProjectDirs;
-use dlib_wrappers::face_detection::FaceDetectorCnn;
-use dlib_wrappers::face_encoding::FaceEncodingNetwork;
-use dlib_wrappers::landmark_prediction::LandmarkPredictor;
+use dlib_wrappers::face_chip::FaceChipExtractor;
 use indicatif::ProgressState;
 use once_cell::sync::Lazy;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::thread;
 use std::time::Instant;
-use tracing::info;
 use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
 use tracing_subscriber::fmt::format;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use walkdir::WalkDir;
 
+mod error;
+mod face_clustering;
+mod face_crops;
+mod face_quality;
 mod face_recognizer;
+mod file_walker;
+mod head_pose;
 mod image_helpers;
+mod models;
 mod otel;
 mod person_registry;
+mod pipeline;
 
 static PROJECT_DIRS: Lazy<ProjectDirs> = Lazy::new(|| {
     ProjectDirs::from("com", "example", "face-recognizer")
@@ -53,12 +73,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with(LevelFilter::INFO)
         .init();
 
-    let persons_registry = PersonRegistrySqlite::initialize().await;
-    let models = Arc::new(DefaultModels::default());
-    let recognizer = FaceRecognizer::new(models.clone(), persons_registry.clone());
-
     let cmd = clap::Command::new("face-recognizer")
         .subcommand_required(true)
+        .arg(
+            clap::arg!(--"models-dir" <DIR> "a directory with the dlib model files")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
         .subcommand(
             clap::command!("recognize").args(&[
                 clap::arg!(<input> "a path to a file to analyse")
@@ -67,62 +88,456 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Arg::new("skip-processed-check")
                     .long("skip-processed-check")
                     .action(ArgAction::SetTrue),
-            ]),
+                clap::arg!(--"neighbours" <K> "number of nearest labeled faces voting on identity")
+                    .value_parser(clap::value_parser!(u32).range(1..))
+                    .default_value("5"),
+                clap::arg!(--"match-distance" <DISTANCE>)
+                    .help("maximum distance of a labeled face to count as a vote")
+                    .value_parser(clap::value_parser!(f32))
+                    .default_value("0.6"),
+                clap::arg!(--"match-max-angle" <DEGREES>)
+                    .help("do not match faces turned further left, right, up or down")
+                    .value_parser(clap::value_parser!(f64)),
+                clap::arg!(--"min-quality" <SCORE>)
+                    .help("do not match faces of lower quality, between 0 and 1")
+                    .value_parser(clap::value_parser!(f64)),
+                clap::arg!(--"jobs" <N>)
+                    .help("number of files analysed in parallel, defaults to the number of CPUs")
+                    .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+                clap::arg!(--"include" <GLOB> "only analyse files matching the glob")
+                    .action(ArgAction::Append),
+                clap::arg!(--"exclude" <GLOB> "skip files matching the glob")
+                    .action(ArgAction::Append),
+                clap::arg!(--"max-depth" <DEPTH> "maximum depth of directories to walk into")
+                    .value_parser(clap::value_parser!(usize)),
+                Arg::new("follow-symlinks")
+                    .long("follow-symlinks")
+                    .action(ArgAction::SetTrue),
+                Arg::new("hidden")
+                    .long("hidden")
+                    .help("include hidden files and directories")
+                    .action(ArgAction::SetTrue),
+                Arg::new("annotate")
+                    .long("annotate")
+                    .value_name("DIR")
+                    .help(
+                        "write copies of the images with faces outlined, next to them or into DIR",
+                    )
+                    .num_args(0..=1)
+                    .require_equals(true)
+                    .value_parser(clap::value_parser!(PathBuf)),
+                detector_arg(),
+                landmarks_arg(),
+                clap::arg!(--"jitters" <N>)
+                    .help("average encodings of N jittered copies of each face, slow but accurate")
+                    .value_parser(clap::value_parser!(u32))
+                    .default_value("0"),
+                clap::arg!(--"encode-batch" <N> "number of faces encoded at once")
+                    .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                    .default_value("16"),
+            ])
+            .args(detection_args()),
         )
         .subcommand(clap::command!("locate").args(&[
-            clap::arg!(<ID> "a path to a file to analyse").value_parser(clap::value_parser!(i64)),
-        ]));
+            clap::arg!(<ID> "an id of a face to find similar faces for")
+                .value_parser(clap::value_parser!(i64)),
+            clap::arg!(--"limit" <K> "maximum number of faces to list")
+                .value_parser(clap::value_parser!(u32).range(1..MAX_KNN_K as i64))
+                .default_value("30"),
+            clap::arg!(--"max-distance" <DISTANCE> "maximum distance of a listed face")
+                .value_parser(clap::value_parser!(f32))
+                .default_value("0.6"),
+            clap::arg!(--"min-quality" <SCORE>)
+                .help("leave out faces of lower quality, between 0 and 1")
+                .value_parser(clap::value_parser!(f64)),
+        ]))
+        .subcommand(clap::command!("search").args(&[
+            clap::arg!(<input> "a path to a photo with faces to look for")
+                .value_parser(clap::value_parser!(PathBuf)),
+            clap::arg!(--"limit" <K>)
+                .help("maximum number of matching faces per query face, 4096 by default")
+                .value_parser(clap::value_parser!(u32).range(1..=MAX_KNN_K as i64)),
+            clap::arg!(--"max-distance" <DISTANCE> "maximum distance of a matching face")
+                .value_parser(clap::value_parser!(f32))
+                .default_value("0.6"),
+            clap::arg!(--"min-quality" <SCORE>)
+                .help("leave out faces of lower quality, between 0 and 1")
+                .value_parser(clap::value_parser!(f64)),
+            detector_arg(),
+            landmarks_arg(),
+        ]).args(detection_args()))
+        .subcommand(clap::command!("cluster").args(&[
+            clap::arg!(--"neighbours" <K> "number of nearest faces each face is linked to")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("100"),
+            clap::arg!(--"max-distance" <DISTANCE> "maximum distance between linked faces")
+                .value_parser(clap::value_parser!(f32))
+                .default_value("0.5"),
+            clap::arg!(--"min-size" <SIZE> "minimum number of faces in a stored cluster")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("2"),
+            clap::arg!(--"min-quality" <SCORE>)
+                .help("leave out faces of lower quality, between 0 and 1")
+                .value_parser(clap::value_parser!(f64)),
+        ]))
+        .subcommand(clap::command!("name").args(&[
+            clap::arg!(<FACE_ID> "an id of a detected face, or of a cluster with --cluster")
+                .value_parser(clap::value_parser!(i64)),
+            clap::arg!(<NAME> "a name of the person"),
+            Arg::new("cluster")
+                .long("cluster")
+                .help("treat the id as a cluster id and name all its unlabeled faces")
+                .action(ArgAction::SetTrue),
+        ]))
+        .subcommand(
+            clap::command!("photos").args(&[
+                clap::arg!(<NAME> "a name of the person"),
+                clap::arg!(--"max-angle" <DEGREES>)
+                    .help("only list faces turned at most this far, e.g. 15 for frontal ones")
+                    .value_parser(clap::value_parser!(f64)),
+            ]),
+        )
+        .subcommand(
+            clap::command!("crops")
+                .args(&[
+                    clap::arg!(<OUTPUT> "a directory to write the aligned face images to")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                    clap::arg!(--"person" <NAME> "crop all faces of the person"),
+                    clap::arg!(--"face" <FACE_ID> "crop a single face")
+                        .value_parser(clap::value_parser!(i64)),
+                    clap::arg!(--"size" <PIXELS> "width and height of the face images")
+                        .value_parser(
+                            clap::value_parser!(u32).range(1..=FaceChipExtractor::MAX_SIZE as i64),
+                        )
+                        .default_value("150"),
+                    clap::arg!(--"padding" <FRACTION>)
+                        .help("margin around the face as a fraction of its size, at least 0")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("0.25"),
+                ])
+                .group(
+                    ArgGroup::new("faces")
+                        .args(["person", "face"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::command!("models")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::command!("verify")
+                        .about("check the model files against their hashes")
+                        .args(&[detector_arg(), landmarks_arg()]),
+                ),
+        );
+
+    let matches = cmd.get_matches();
+
+    let model_manager = ModelManager::locate(
+        matches
+            .get_one::<PathBuf>("models-dir")
+            .map(PathBuf::as_path),
+    );
+    let persons_registry = PersonRegistrySqlite::initialize().await?;
+    // loading the networks takes a while, so only do it for the commands analysing images
+    let load_recognizer = |matches: &ArgMatches| -> Result<Arc<FaceRecognizer>, RecognizeError> {
+        let detector_mode = *matches.get_one::<DetectorMode>("detector").unwrap();
+        let landmark_points = *matches.get_one::<LandmarkPoints>("landmarks").unwrap();
+        let models = Arc::new(model_manager.load(detector_mode, landmark_points)?);
+        Ok(Arc::new(FaceRecognizer::new(
+            models,
+            persons_registry.clone(),
+        )))
+    };
 
-    match cmd.get_matches().subcommand() {
+    match matches.subcommand() {
         Some(("recognize", matches)) => {
+            let recognizer = load_recognizer(matches)?;
             let recognize_start = Instant::now();
 
             let input = matches.get_one::<PathBuf>("input").unwrap();
             let skip_processed_check = matches.get_flag("skip-processed-check");
+            let match_neighbours = *matches.get_one::<u32>("neighbours").unwrap();
+            let match_max_distance = *matches.get_one::<f32>("match-distance").unwrap();
+
+            let annotate = match matches.get_one::<PathBuf>("annotate") {
+                Some(dir) => {
+                    fs::create_dir_all(dir)?;
+                    // images keep their place within the walked directory
+                    let root = if input.is_dir() {
+                        input.clone()
+                    } else {
+                        input.parent().unwrap_or(Path::new("")).to_path_buf()
+                    };
+                    Some(AnnotateTarget::Directory {
+                        output: dir.clone(),
+                        root,
+                    })
+                }
+                None if matches.contains_id("annotate") => Some(AnnotateTarget::NextToInput),
+                None => None,
+            };
 
             let options = FaceRecognizerOptions {
                 skip_processed_check,
+                match_neighbours,
+                match_max_distance,
+                match_max_angle: matches.get_one::<f64>("match-max-angle").copied(),
+                match_min_quality: matches.get_one::<f64>("min-quality").copied(),
+                annotate,
+                detection: detection_options(matches),
+                encoding: EncodingOptions {
+                    num_jitters: *matches.get_one::<u32>("jitters").unwrap(),
+                    batch_size: *matches.get_one::<usize>("encode-batch").unwrap(),
+                },
             };
 
+            let jobs = matches
+                .get_one::<usize>("jobs")
+                .copied()
+                .unwrap_or_else(|| {
+                    thread::available_parallelism()
+                        .map(|n| n.get())
+                        .unwrap_or(1)
+                });
+
+            let mut failures = Vec::new();
+
             if input.is_dir() {
-                for entry in WalkDir::new(input)
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| e.path().is_file())
-                {
-                    let path = entry.path().to_path_buf();
-                    match recognizer.process_file(&path, options).await {
-                        DetectResult::Skipped => {}
-                        DetectResult::NoFaces => info!("no faces found in {}", path.display()),
-                        DetectResult::FacesDetected(face_ids) => {
-                            info!("found {} faces in {}", face_ids.len(), path.display());
-
-                            for face_id in face_ids {
-                                let similar_face_ids = persons_registry.locate_similar(face_id).await;
-
-                                if !similar_face_ids.is_empty() {
-                                    info!("I do not recognize the person.. Could you tell who that is?");
-                                    // persons_registry.
-                                } else {
-                                    info!("no similar faces found for face id {}", face_id);
-                                }
-                            }
-                        }
+                let walk_options = WalkOptions {
+                    include: build_glob_set(
+                        matches.get_many::<String>("include").unwrap_or_default(),
+                    )?,
+                    exclude: build_glob_set(
+                        matches.get_many::<String>("exclude").unwrap_or_default(),
+                    )?,
+                    max_depth: matches.get_one::<usize>("max-depth").copied(),
+                    follow_symlinks: matches.get_flag("follow-symlinks"),
+                    hidden: matches.get_flag("hidden"),
+                    skip_annotated_copies: matches!(
+                        options.annotate,
+                        Some(AnnotateTarget::NextToInput)
+                    ),
+                    excluded_dir: match &options.annotate {
+                        Some(AnnotateTarget::Directory { output, .. }) => Some(output.clone()),
+                        _ => None,
+                    },
+                };
+
+                let (paths, walk_failures) = collect_images(input, &walk_options);
+                for (path, err) in walk_failures {
+                    warn!("failed to read {}", err);
+                    failures.push((path, err));
+                }
+
+                let mut results =
+                    pipeline::recognize_files(paths, recognizer.clone(), options.clone(), jobs);
+
+                while let Some((path, result)) = results.recv().await {
+                    let reported = match result {
+                        Ok(result) => report_detect_result(&persons_registry, &path, result).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = reported {
+                        warn!("failed to process {}: {}", path.display(), err);
+                        failures.push((path, err));
                     }
                 }
             } else if input.is_file() {
-                recognizer.process_file(&input, options).await;
+                let reported = match recognizer.process_file(input, &options).await {
+                    Ok(result) => report_detect_result(&persons_registry, input, result).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = reported {
+                    failures.push((input.clone(), err));
+                }
             }
 
             info!(
                 "recognizing faces finished in: {:?}",
                 recognize_start.elapsed()
             );
+
+            if !failures.is_empty() {
+                report_failures(&failures);
+            }
         }
         Some(("locate", matches)) => {
-            let encoding_id = *matches.get_one::<i64>("ID").unwrap();
-            persons_registry.locate_similar(encoding_id).await;
+            let face_id = *matches.get_one::<i64>("ID").unwrap();
+            let limit = *matches.get_one::<u32>("limit").unwrap();
+            let max_distance = *matches.get_one::<f32>("max-distance").unwrap();
+
+            let nearest = persons_registry
+                .find_nearest(
+                    NearestQuery::Face(face_id),
+                    limit,
+                    max_distance,
+                    matches.get_one::<f64>("min-quality").copied(),
+                )
+                .await?;
+
+            if nearest.is_empty() {
+                info!("no similar faces found for face id {}", face_id);
+            }
+
+            for face in nearest {
+                info!(
+                    "{}: distance: {:.4}, file: {}, rect: {:?}",
+                    face.face_id,
+                    face.distance,
+                    face.path.as_deref().unwrap_or("-"),
+                    face.rect
+                );
+            }
+        }
+        Some(("search", matches)) => {
+            let input = matches.get_one::<PathBuf>("input").unwrap();
+            let limit = matches.get_one::<u32>("limit").copied();
+            let max_distance = *matches.get_one::<f32>("max-distance").unwrap();
+
+            let recognizer = load_recognizer(matches)?;
+            let results = recognizer
+                .search(
+                    input,
+                    limit,
+                    max_distance,
+                    matches.get_one::<f64>("min-quality").copied(),
+                    &detection_options(matches),
+                )
+                .await?;
+
+            if results.is_empty() {
+                info!("no faces found in {}", input.display());
+            }
+
+            for (index, query_face) in results.iter().enumerate() {
+                // matches are sorted by distance, so the first face of each photo is its best
+                let mut photos: Vec<(&str, f32)> = Vec::new();
+                for face in &query_face.matches {
+                    let path = face.path.as_deref().unwrap_or("-");
+                    if !photos.iter().any(|(seen, _)| *seen == path) {
+                        photos.push((path, face.distance));
+                    }
+                }
+
+                info!(
+                    "face #{} at {:?} appears in {} photos",
+                    index + 1,
+                    query_face.rect,
+                    photos.len()
+                );
+
+                for (path, distance) in photos {
+                    info!("  {} (distance: {:.4})", path, distance);
+                }
+            }
+        }
+        Some(("cluster", matches)) => {
+            let options = ClusterOptions {
+                neighbours: *matches.get_one::<u32>("neighbours").unwrap(),
+                max_distance: *matches.get_one::<f32>("max-distance").unwrap(),
+                min_size: *matches.get_one::<usize>("min-size").unwrap(),
+                min_quality: matches.get_one::<f64>("min-quality").copied(),
+            };
+
+            let clusters = cluster_unlabeled_faces(&persons_registry, options).await?;
+
+            info!("found {} clusters", clusters.len());
+            for (index, face_ids) in clusters.iter().enumerate() {
+                info!(
+                    "cluster {}: {} faces, e.g. {:?}",
+                    index + 1,
+                    face_ids.len(),
+                    &face_ids[..face_ids.len().min(5)]
+                );
+            }
+        }
+        Some(("name", matches)) => {
+            let id = *matches.get_one::<i64>("FACE_ID").unwrap();
+            let name = matches.get_one::<String>("NAME").unwrap();
+
+            if matches.get_flag("cluster") {
+                match persons_registry.name_cluster(id, name).await? {
+                    Some((person_id, count)) => info!(
+                        "{} faces of cluster {} assigned to {} (person id {})",
+                        count, id, name, person_id
+                    ),
+                    None => info!("cluster {} has no unlabeled faces", id),
+                }
+            } else {
+                match persons_registry.name_face(id, name).await? {
+                    Some(person_id) => {
+                        info!("face {} assigned to {} (person id {})", id, name, person_id)
+                    }
+                    None => info!("face with id {} does not exist", id),
+                }
+            }
+        }
+        Some(("photos", matches)) => {
+            let name = matches.get_one::<String>("NAME").unwrap();
+            let max_angle = matches.get_one::<f64>("max-angle").copied();
+
+            let faces = persons_registry.find_person_faces(name, max_angle).await?;
+
+            if faces.is_empty() {
+                info!("no matching faces of {} found", name);
+            }
+
+            for face in faces {
+                let pose = match face.pose {
+                    Some(pose) => format!(
+                        "yaw: {:.0}, pitch: {:.0}, roll: {:.0}",
+                        pose.yaw, pose.pitch, pose.roll
+                    ),
+                    None => "pose unknown".to_string(),
+                };
+                info!(
+                    "{}: {}, rect: {:?}, {}",
+                    face.face_id,
+                    face.path.as_deref().unwrap_or("-"),
+                    face.rect,
+                    pose
+                );
+            }
+        }
+        Some(("crops", matches)) => {
+            let output_dir = matches.get_one::<PathBuf>("OUTPUT").unwrap();
+            let extractor = FaceChipExtractor::new(
+                *matches.get_one::<u32>("size").unwrap(),
+                *matches.get_one::<f64>("padding").unwrap(),
+            )
+            .map_err(RecognizeError::Model)?;
+            let faces = match matches.get_one::<String>("person") {
+                Some(name) => persons_registry.find_person_faces(name, None).await?,
+                None => {
+                    let face_id = *matches.get_one::<i64>("face").unwrap();
+                    persons_registry
+                        .find_face(face_id)
+                        .await?
+                        .into_iter()
+                        .collect()
+                }
+            };
+
+            if faces.is_empty() {
+                info!("no matching faces found");
+            } else {
+                fs::create_dir_all(output_dir)?;
+                let written =
+                    write_face_crops(&persons_registry, faces, extractor, output_dir).await?;
+                info!("{} faces written to {}", written, output_dir.display());
+            }
         }
+        Some(("models", matches)) => match matches.subcommand() {
+            Some(("verify", matches)) => verify_models(
+                &model_manager,
+                required_models(
+                    *matches.get_one::<DetectorMode>("detector").unwrap(),
+                    *matches.get_one::<LandmarkPoints>("landmarks").unwrap(),
+                ),
+            )?,
+            _ => unreachable!("clap should ensure we don't get here"),
+        },
         _ => unreachable!("clap should ensure we don't get here"),
     };
 
@@ -132,39 +547,169 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn get_output_path(input: &Path) -> PathBuf {
-    let input_filename = input.file_stem().unwrap().to_str().unwrap();
-    let input_ext = input.extension().unwrap().to_str().unwrap();
-    let output = input
-        .parent()
-        .unwrap()
-        .join(format!("{}_new.{}", input_filename, input_ext));
-
-    output
+async fn report_detect_result(
+    registry: &PersonRegistrySqlite,
+    path: &Path,
+    result: DetectResult,
+) -> Result<(), RecognizeError> {
+    let kind = match result {
+        DetectResult::Skipped => return Ok(()),
+        DetectResult::NoFaces => {
+            info!("no faces found in {}", path.display());
+            return Ok(());
+        }
+        DetectResult::FacesDetected(kind) => kind,
+    };
+
+    let mut names = Vec::with_capacity(kind.known_ids().len());
+    for face_id in kind.known_ids() {
+        if let Some(name) = registry.find_person_name(*face_id).await? {
+            names.push(name);
+        }
+    }
+
+    info!(
+        "found {} in {}",
+        describe_faces(names, kind.unknown_ids().len()),
+        path.display()
+    );
+
+    for face_id in kind.unknown_ids() {
+        info!(
+            "I do not recognize face {}.. Could you tell who that is? (use `name {} <NAME>`)",
+            face_id, face_id
+        );
+    }
+
+    Ok(())
 }
 
-struct DefaultModels {
-    face_detector: FaceDetectorCnn,
-    landmarks_predictor: LandmarkPredictor,
-    face_encoding: FaceEncodingNetwork,
+fn detector_arg() -> Arg {
+    Arg::new("detector")
+        .long("detector")
+        .help("face detector to use, hog-then-cnn runs cnn only when hog finds no faces")
+        .value_parser(
+            PossibleValuesParser::new(DetectorMode::NAMES)
+                .map(|name| name.parse::<DetectorMode>().unwrap()),
+        )
+        .default_value("cnn")
 }
 
-impl Default for DefaultModels {
-    fn default() -> Self {
-        Self {
-            // detector: FaceDetector::default(),
-            face_detector: FaceDetectorCnn::new(
-                "crates/dlib_wrappers/files/mmod_human_face_detector.dat",
-            )
-            .expect("Failed to load CNN face detector"),
-            landmarks_predictor: LandmarkPredictor::new(
-                "crates/dlib_wrappers/files/shape_predictor_68_face_landmarks.dat",
-            )
-            .expect("Failed to load landmark predictor"),
-            face_encoding: FaceEncodingNetwork::new(
-                "crates/dlib_wrappers/files/dlib_face_recognition_resnet_model_v1.dat",
-            )
-            .expect("Failed to load face encoding network"),
+fn landmarks_arg() -> Arg {
+    Arg::new("landmarks")
+        .long("landmarks")
+        .help("number of face landmarks to predict, the 5 point model is smaller and faster")
+        .value_parser(
+            PossibleValuesParser::new(LandmarkPoints::NAMES)
+                .map(|name| name.parse::<LandmarkPoints>().unwrap()),
+        )
+        .default_value("68")
+}
+
+/// Arguments of finding faces, read back by [`detection_options`].
+fn detection_args() -> [Arg; 4] {
+    [
+        clap::arg!(--"min-confidence" <SCORE>)
+            .help("drop faces the detector scored lower, scores of hog and cnn differ")
+            .value_parser(clap::value_parser!(f64)),
+        clap::arg!(--"upsample" <N>)
+            .help("double the images N times before detection to find small faces")
+            .value_parser(clap::value_parser!(u32).range(0..=4))
+            .default_value("0"),
+        clap::arg!(--"max-dimension" <PIXELS>)
+            .help("detect faces in larger images downscaled to this width or height")
+            .value_parser(clap::value_parser!(u32).range(1..)),
+        Arg::new("retry-rotations")
+            .long("retry-rotations")
+            .help("look for faces in rotated images when none are found, e.g. in scans")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+fn detection_options(matches: &ArgMatches) -> DetectionOptions {
+    DetectionOptions {
+        min_confidence: matches.get_one::<f64>("min-confidence").copied(),
+        upsample_times: *matches.get_one::<u32>("upsample").unwrap(),
+        max_dimension: matches.get_one::<u32>("max-dimension").copied(),
+        retry_rotations: matches.get_flag("retry-rotations"),
+    }
+}
+
+/// Checks all installed models, failing when one of them or a `required` one is not valid.
+fn verify_models(
+    model_manager: &ModelManager,
+    required: Vec<ModelKind>,
+) -> Result<(), RecognizeError> {
+    info!("verifying models in {}", model_manager.dir().display());
+
+    let mut failed = 0;
+    for (kind, status) in model_manager.verify()? {
+        let file_name = kind.file_name();
+        match status {
+            ModelStatus::Valid => info!("  {}: ok", file_name),
+            // which models are needed depends on --detector and --landmarks
+            ModelStatus::Missing if required.contains(&kind) => {
+                failed += 1;
+                warn!("  {}: missing", file_name)
+            }
+            ModelStatus::Missing => info!("  {}: not installed", file_name),
+            ModelStatus::Unknown { actual } => {
+                failed += 1;
+                warn!("  {}: no known hash to check, actual {}", file_name, actual)
+            }
+            ModelStatus::Mismatch { expected, actual } => {
+                failed += 1;
+                warn!(
+                    "  {}: hash mismatch, expected {}, actual {}",
+                    file_name, expected, actual
+                )
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(RecognizeError::Model(format!(
+            "{} models failed verification",
+            failed
+        )));
+    }
+
+    Ok(())
+}
+
+fn report_failures(failures: &[(PathBuf, RecognizeError)]) {
+    warn!("failed to process {} files:", failures.len());
+
+    for (path, err) in failures {
+        match err.path() {
+            // the error already says which file it is about
+            Some(_) => warn!("  {}", err),
+            None => warn!("  {}: {}", path.display(), err),
         }
     }
 }
+
+/// Builds a sentence like "Alice, Bob and 1 unknown face".
+fn describe_faces(mut parts: Vec<String>, unknown_count: usize) -> String {
+    match unknown_count {
+        0 => {}
+        1 => parts.push("1 unknown face".to_string()),
+        n => parts.push(format!("{} unknown faces", n)),
+    }
+
+    match parts.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+fn get_output_path(input: &Path) -> PathBuf {
+    let input_filename = input.file_stem().unwrap_or_default().to_string_lossy();
+    // images recognized by their content may have no extension, so save those as png
+    let input_ext = input
+        .extension()
+        .map_or("png".into(), |ext| ext.to_string_lossy());
+
+    input.with_file_name(format!("{}_new.{}", input_filename, input_ext))
+}
@@ -0,0 +1,10 @@
+CREATE TABLE Persons
+(
+    Id        INTEGER PRIMARY KEY AUTOINCREMENT,
+    Name      TEXT NOT NULL UNIQUE,
+    CreatedAt DATETIME DEFAULT (CURRENT_TIMESTAMP)
+);
+
+ALTER TABLE Faces ADD COLUMN PersonId INTEGER REFERENCES Persons (Id);
+
+CREATE INDEX IX_Faces_PersonId ON Faces (PersonId);
@@ -0,0 +1,12 @@
+-- PersonId is a metadata column so KNN queries can be restricted to labeled faces.
+-- vec0 metadata columns cannot hold NULL, so 0 stands for "unlabeled".
+CREATE VIRTUAL TABLE FaceEncodings USING vec0
+(
+    FaceId       INTEGER PRIMARY KEY,
+    FaceEncoding float[128],
+    PersonId     INTEGER
+);
+
+INSERT INTO FaceEncodings (FaceId, FaceEncoding, PersonId)
+SELECT Id, FaceEncoding, coalesce(PersonId, 0)
+FROM Faces;
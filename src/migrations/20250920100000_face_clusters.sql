@@ -0,0 +1,3 @@
+ALTER TABLE Faces ADD COLUMN ClusterId INTEGER;
+
+CREATE INDEX IX_Faces_ClusterId ON Faces (ClusterId);
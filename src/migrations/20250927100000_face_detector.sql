@@ -0,0 +1,4 @@
+ALTER TABLE Faces ADD COLUMN Detector TEXT;
+
+-- faces stored so far were all found by the CNN detector
+UPDATE Faces SET Detector = 'cnn';
@@ -0,0 +1,2 @@
+-- score of the detector which found the face, unknown for faces stored before it was kept
+ALTER TABLE Faces ADD COLUMN Confidence REAL;
@@ -0,0 +1,3 @@
+-- EXIF orientation (1-8) applied to the image before detection, so rectangles of the file's faces
+-- are in the coordinates of the image as viewers display it; unknown for files stored before
+ALTER TABLE ProcessedFiles ADD COLUMN Orientation INTEGER;
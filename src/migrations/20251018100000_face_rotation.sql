@@ -0,0 +1,3 @@
+-- clockwise rotation in degrees of the image in which the face was found, the rectangle is always
+-- in the coordinates of the image itself
+ALTER TABLE Faces ADD COLUMN Rotation INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- landmark points in image coordinates, as pairs of little endian 32-bit integers x, y;
+-- NULL for faces stored before they were kept
+ALTER TABLE Faces ADD COLUMN Landmarks BLOB;
@@ -0,0 +1,5 @@
+-- head pose in degrees, see head_pose.rs for the signs; NULL when it could not be estimated,
+-- e.g. for 5 point landmarks
+ALTER TABLE Faces ADD COLUMN Yaw REAL;
+ALTER TABLE Faces ADD COLUMN Pitch REAL;
+ALTER TABLE Faces ADD COLUMN Roll REAL;
@@ -0,0 +1,2 @@
+-- score between 0 and 1, see face_quality.rs; NULL for faces stored before it was computed
+ALTER TABLE Faces ADD COLUMN Quality REAL;
@@ -0,0 +1,2 @@
+-- number of jittered copies averaged into the encoding, faces stored before were not jittered
+ALTER TABLE Faces ADD COLUMN Jitters INTEGER NOT NULL DEFAULT 0;
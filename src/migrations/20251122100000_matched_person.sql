@@ -0,0 +1,5 @@
+-- person a face was matched to automatically, kept apart from PersonId so that only manual
+-- labels vote on later matches; faces matched before this column existed stay in PersonId
+ALTER TABLE Faces ADD COLUMN MatchedPersonId INTEGER REFERENCES Persons (Id);
+
+CREATE INDEX IX_Faces_MatchedPersonId ON Faces (MatchedPersonId);
@@ -0,0 +1,16 @@
+-- Quality is a metadata column so that KNN queries can skip low quality faces instead of
+-- spending their k neighbours on them. vec0 tables cannot be altered, so the table is
+-- rebuilt; faces stored before quality was computed get 1, so no minimum leaves them out.
+DROP TABLE FaceEncodings;
+
+CREATE VIRTUAL TABLE FaceEncodings USING vec0
+(
+    FaceId       INTEGER PRIMARY KEY,
+    FaceEncoding float[128],
+    PersonId     INTEGER,
+    Quality      FLOAT
+);
+
+INSERT INTO FaceEncodings (FaceId, FaceEncoding, PersonId, Quality)
+SELECT Id, FaceEncoding, coalesce(PersonId, 0), coalesce(Quality, 1.0)
+FROM Faces;
@@ -0,0 +1,361 @@
+//! Locating, loading and verifying the dlib model files.
+//!
+//! The models directory is taken from `--models-dir`, then from the `FACE_RECOGNIZER_MODELS_DIR`
+//! environment variable, and defaults to `models` in the project data directory.
+
+use crate::PROJECT_DIRS;
+use crate::error::RecognizeError;
+use crate::face_recognizer::FaceRecognizer;
+use dlib_wrappers::face_detection::{FaceDetector, FaceDetectorCnn};
+use dlib_wrappers::face_encoding::FaceEncodingNetwork;
+use dlib_wrappers::landmark_prediction::{LandmarkModel, LandmarkPredictor};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub(crate) const MODELS_DIR_ENV: &str = "FACE_RECOGNIZER_MODELS_DIR";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ModelKind {
+    FaceDetector,
+    LandmarkPredictor5,
+    LandmarkPredictor68,
+    FaceEncoding,
+}
+
+impl ModelKind {
+    pub(crate) const ALL: [ModelKind; 4] = [
+        ModelKind::FaceDetector,
+        ModelKind::LandmarkPredictor5,
+        ModelKind::LandmarkPredictor68,
+        ModelKind::FaceEncoding,
+    ];
+
+    pub(crate) fn file_name(self) -> &'static str {
+        match self {
+            ModelKind::FaceDetector => "mmod_human_face_detector.dat",
+            ModelKind::LandmarkPredictor5 => "shape_predictor_5_face_landmarks.dat",
+            ModelKind::LandmarkPredictor68 => "shape_predictor_68_face_landmarks.dat",
+            ModelKind::FaceEncoding => "dlib_face_recognition_resnet_model_v1.dat",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            ModelKind::FaceDetector => "face detector",
+            ModelKind::LandmarkPredictor5 => "5 point landmark predictor",
+            ModelKind::LandmarkPredictor68 => "68 point landmark predictor",
+            ModelKind::FaceEncoding => "face encoding network",
+        }
+    }
+
+    fn download_url(self) -> String {
+        format!("http://dlib.net/files/{}.bz2", self.file_name())
+    }
+
+    /// Blake3 hash of the unpacked file published at [`Self::download_url`].
+    ///
+    /// `None` while the hash of the published file is not recorded here, in which case the
+    /// model cannot be verified.
+    pub(crate) fn expected_hash(self) -> Option<&'static str> {
+        // todo: record the hashes of the files published on dlib.net
+        match self {
+            ModelKind::FaceDetector => None,
+            ModelKind::LandmarkPredictor5 => None,
+            ModelKind::LandmarkPredictor68 => None,
+            ModelKind::FaceEncoding => None,
+        }
+    }
+}
+
+/// Which detectors look for faces in an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DetectorMode {
+    Hog,
+    Cnn,
+    /// Runs the fast HOG detector and falls back to the CNN one only when HOG finds nothing.
+    HogThenCnn,
+}
+
+impl DetectorMode {
+    pub(crate) const NAMES: [&'static str; 3] = ["hog", "cnn", "hog-then-cnn"];
+
+    fn needs_cnn(self) -> bool {
+        self != DetectorMode::Hog
+    }
+}
+
+impl FromStr for DetectorMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "hog" => Ok(DetectorMode::Hog),
+            "cnn" => Ok(DetectorMode::Cnn),
+            "hog-then-cnn" => Ok(DetectorMode::HogThenCnn),
+            _ => Err(format!("unknown detector '{}'", name)),
+        }
+    }
+}
+
+/// How many landmarks are predicted for each face.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum LandmarkPoints {
+    /// Eye corners and the bottom of the nose, enough to align faces for encoding.
+    Five,
+    SixtyEight,
+}
+
+impl LandmarkPoints {
+    pub(crate) const NAMES: [&'static str; 2] = ["5", "68"];
+
+    pub(crate) fn count(self) -> usize {
+        match self {
+            LandmarkPoints::Five => 5,
+            LandmarkPoints::SixtyEight => 68,
+        }
+    }
+
+    fn model(self) -> ModelKind {
+        match self {
+            LandmarkPoints::Five => ModelKind::LandmarkPredictor5,
+            LandmarkPoints::SixtyEight => ModelKind::LandmarkPredictor68,
+        }
+    }
+}
+
+impl FromStr for LandmarkPoints {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "5" => Ok(LandmarkPoints::Five),
+            "68" => Ok(LandmarkPoints::SixtyEight),
+            _ => Err(format!("unsupported number of landmarks '{}'", name)),
+        }
+    }
+}
+
+/// The detector which found a face, stored with it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Detector {
+    Hog,
+    Cnn,
+}
+
+impl Detector {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Detector::Hog => "hog",
+            Detector::Cnn => "cnn",
+        }
+    }
+}
+
+/// Outcome of checking a single model file against its expected hash.
+#[derive(Debug)]
+pub(crate) enum ModelStatus {
+    Valid,
+    Missing,
+    /// No hash is known for the model, so only its actual hash can be reported.
+    Unknown {
+        actual: String,
+    },
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+#[derive(Clone)]
+pub(crate) struct DefaultModels {
+    pub(crate) detector_mode: DetectorMode,
+    pub(crate) hog_detector: FaceDetector,
+    /// Loaded only when the detector mode uses it.
+    pub(crate) cnn_detector: Option<FaceDetectorCnn>,
+    pub(crate) landmarks_predictor: LandmarkPredictor,
+    pub(crate) face_encoding: FaceEncodingNetwork,
+}
+
+/// Knows where the model files live.
+#[derive(Clone, Debug)]
+pub(crate) struct ModelManager {
+    dir: PathBuf,
+}
+
+/// Models loaded for the detector mode and number of landmarks.
+pub(crate) fn required_models(
+    detector_mode: DetectorMode,
+    landmark_points: LandmarkPoints,
+) -> Vec<ModelKind> {
+    let mut models = vec![landmark_points.model(), ModelKind::FaceEncoding];
+    if detector_mode.needs_cnn() {
+        models.push(ModelKind::FaceDetector);
+    }
+
+    models
+}
+
+impl ModelManager {
+    /// Picks the models directory, `models_dir` being the value of `--models-dir`, if given.
+    pub(crate) fn locate(models_dir: Option<&Path>) -> Self {
+        let dir = match models_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => match env::var_os(MODELS_DIR_ENV) {
+                Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+                _ => PROJECT_DIRS.data_dir().join("models"),
+            },
+        };
+
+        Self { dir }
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) fn path(&self, kind: ModelKind) -> PathBuf {
+        self.dir.join(kind.file_name())
+    }
+
+    /// Path of the model, or an error explaining where it was expected and how to get it.
+    fn require(&self, kind: ModelKind) -> Result<PathBuf, RecognizeError> {
+        let path = self.path(kind);
+        if path.is_file() {
+            return Ok(path);
+        }
+
+        Err(RecognizeError::Model(format!(
+            "{} model not found at {}; download and unpack {} or point --models-dir or {} \
+             at the directory containing {}",
+            kind.description(),
+            path.display(),
+            kind.download_url(),
+            MODELS_DIR_ENV,
+            kind.file_name()
+        )))
+    }
+
+    pub(crate) fn load(
+        &self,
+        detector_mode: DetectorMode,
+        landmark_points: LandmarkPoints,
+    ) -> Result<DefaultModels, RecognizeError> {
+        let model_error = |kind: ModelKind| {
+            move |message: String| {
+                RecognizeError::Model(format!("cannot load {}: {}", kind.description(), message))
+            }
+        };
+
+        let face_detector_path = detector_mode
+            .needs_cnn()
+            .then(|| self.require(ModelKind::FaceDetector))
+            .transpose()?;
+        let landmarks_predictor_path = self.require(landmark_points.model())?;
+        let face_encoding_path = self.require(ModelKind::FaceEncoding)?;
+
+        let landmarks_predictor = LandmarkPredictor::new(&landmarks_predictor_path)
+            .map_err(model_error(landmark_points.model()))?;
+        // a predictor of another shape loads fine, but breaks everything relying on the points
+        if landmarks_predictor.num_parts() != landmark_points.count() {
+            return Err(RecognizeError::Model(format!(
+                "{} predicts {} landmarks, but {} were expected",
+                landmarks_predictor_path.display(),
+                landmarks_predictor.num_parts(),
+                landmark_points.count()
+            )));
+        }
+
+        Ok(DefaultModels {
+            detector_mode,
+            hog_detector: FaceDetector::new(),
+            cnn_detector: face_detector_path
+                .map(FaceDetectorCnn::new)
+                .transpose()
+                .map_err(model_error(ModelKind::FaceDetector))?,
+            landmarks_predictor,
+            face_encoding: FaceEncodingNetwork::new(face_encoding_path)
+                .map_err(model_error(ModelKind::FaceEncoding))?,
+        })
+    }
+
+    /// Hashes every model and compares it with the hash of the published file.
+    pub(crate) fn verify(&self) -> Result<Vec<(ModelKind, ModelStatus)>, RecognizeError> {
+        ModelKind::ALL
+            .into_iter()
+            .map(|kind| Ok((kind, check_model(&self.path(kind), kind.expected_hash())?)))
+            .collect()
+    }
+}
+
+/// Hashes the model file and compares it with `expected`, a hex encoded blake3 hash.
+fn check_model(path: &Path, expected: Option<&str>) -> Result<ModelStatus, RecognizeError> {
+    if !path.is_file() {
+        return Ok(ModelStatus::Missing);
+    }
+
+    let actual = FaceRecognizer::calc_hash(path)?.to_hex().to_string();
+    let status = match expected {
+        None => ModelStatus::Unknown { actual },
+        Some(expected) if expected.eq_ignore_ascii_case(&actual) => ModelStatus::Valid,
+        Some(expected) => ModelStatus::Mismatch {
+            expected: expected.to_string(),
+            actual,
+        },
+    };
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn model_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("{}-{}.dat", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_check_model_with_expected_hash() {
+        let path = model_file("valid-model", b"weights");
+        let expected = blake3::hash(b"weights").to_hex().to_string();
+
+        let status = check_model(&path, Some(&expected.to_uppercase())).unwrap();
+
+        assert!(matches!(status, ModelStatus::Valid));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_model_with_wrong_hash() {
+        let path = model_file("changed-model", b"other weights");
+        let expected = blake3::hash(b"weights").to_hex().to_string();
+
+        let status = check_model(&path, Some(&expected)).unwrap();
+
+        match status {
+            ModelStatus::Mismatch {
+                expected: reported,
+                actual,
+            } => {
+                assert_eq!(reported, expected);
+                assert_eq!(actual, blake3::hash(b"other weights").to_hex().to_string());
+            }
+            status => panic!("expected a mismatch, got {:?}", status),
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_missing_model() {
+        let path = env::temp_dir().join("missing-model.dat");
+
+        assert!(matches!(
+            check_model(&path, None).unwrap(),
+            ModelStatus::Missing
+        ));
+    }
+}
@@ -1,18 +1,25 @@
 use crate::PROJECT_DIRS;
+use crate::error::RecognizeError;
+use crate::head_pose::HeadPose;
 use blake3::Hash;
-use dlib_wrappers::Rectangle;
 use dlib_wrappers::face_encoding::FaceEncoding;
+use dlib_wrappers::{Point, Rectangle};
 use sqlite_vec::sqlite3_vec_init;
 use sqlx::types::chrono::{DateTime, Utc};
-use sqlx::{Pool, Sqlite, sqlite::SqlitePoolOptions};
+use sqlx::{Pool, Sqlite, Transaction, sqlite::SqlitePoolOptions};
+use std::ffi::{c_char, c_int};
 use std::fs;
 use std::fs::OpenOptions;
+use std::io;
 use std::path::Path;
 use tracing::info;
 use zerocopy::IntoBytes;
 
 type Db = Pool<Sqlite>;
 
+/// Largest `k` sqlite-vec accepts in a KNN query.
+pub(crate) const MAX_KNN_K: u32 = 4096;
+
 #[derive(Clone)]
 pub(crate) struct PersonRegistrySqlite {
     db: Db,
@@ -21,141 +28,665 @@ pub(crate) struct PersonRegistrySqlite {
 pub struct ProcessedFileInsert {
     pub hash: Hash,
     pub path: String,
+    /// EXIF orientation applied before detection.
+    pub orientation: u8,
 }
 
 impl ProcessedFileInsert {
-    pub fn new(hash: Hash, path: &Path) -> Self {
-        let canonical_path = fs::canonicalize(&path)
-            .unwrap()
+    pub fn new(hash: Hash, path: &Path, orientation: u8) -> Result<Self, RecognizeError> {
+        let canonical_path = fs::canonicalize(path)
+            .map_err(|err| RecognizeError::io(path, err))?
             .to_string_lossy()
             .to_string();
 
-        Self {
+        Ok(Self {
             hash,
             path: canonical_path,
-        }
+            orientation,
+        })
     }
 }
 
+/// A detected face to store with [`PersonRegistrySqlite::add_faces`].
+pub struct FaceInsert<'a> {
+    pub location: Rectangle,
+    pub encoding: &'a FaceEncoding,
+    /// Number of jittered copies averaged into the encoding.
+    pub jitters: u32,
+    /// Name of the detector which found the face.
+    pub detector: &'a str,
+    pub confidence: f64,
+    /// Clockwise rotation in degrees of the image the face was found in.
+    pub rotation: u32,
+    pub landmarks: &'a [Point],
+    pub pose: Option<HeadPose>,
+    pub quality: f64,
+}
+
+/// What [`PersonRegistrySqlite::find_nearest`] measures distances from.
+pub enum NearestQuery<'a> {
+    /// An encoding of an already stored face.
+    Face(i64),
+    Encoding(&'a FaceEncoding),
+}
+
+#[derive(Debug)]
+pub struct NearestFace {
+    pub face_id: i64,
+    pub distance: f32,
+    pub path: Option<String>,
+    pub rect: Rectangle,
+}
+
+/// A face assigned to a person, see [`PersonRegistrySqlite::find_person_faces`].
+#[derive(Debug)]
+pub struct PersonFace {
+    pub face_id: i64,
+    pub path: Option<String>,
+    pub rect: Rectangle,
+    pub pose: Option<HeadPose>,
+}
+
 impl PersonRegistrySqlite {
-    pub async fn find_file(&self, hash: &Hash) -> Option<(i64, String, DateTime<Utc>)> {
-        sqlx::query_as("SELECT Id, Path, ProcessedAt FROM ProcessedFiles WHERE Hash = $1")
-            .bind(&hash.as_bytes()[..])
-            .fetch_optional(&self.db)
-            .await
-            .unwrap()
+    pub async fn find_file(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(i64, String, DateTime<Utc>)>, RecognizeError> {
+        let file =
+            sqlx::query_as("SELECT Id, Path, ProcessedAt FROM ProcessedFiles WHERE Hash = $1")
+                .bind(&hash.as_bytes()[..])
+                .fetch_optional(&self.db)
+                .await?;
+
+        Ok(file)
     }
 
-    pub async fn add_file(&self, file: ProcessedFileInsert) -> i64 {
-        let res = sqlx::query("INSERT INTO ProcessedFiles (Hash, Path) VALUES ($1, $2)")
-            .bind(&file.hash.as_bytes()[..])
-            .bind(&file.path)
+    pub async fn add_file(&self, file: ProcessedFileInsert) -> Result<i64, RecognizeError> {
+        let res =
+            sqlx::query("INSERT INTO ProcessedFiles (Hash, Path, Orientation) VALUES ($1, $2, $3)")
+                .bind(&file.hash.as_bytes()[..])
+                .bind(&file.path)
+                .bind(file.orientation)
+                .execute(&self.db)
+                .await?;
+
+        Ok(res.last_insert_rowid())
+    }
+
+    /// Records the EXIF orientation applied to an already stored file.
+    pub async fn set_file_orientation(
+        &self,
+        file_id: i64,
+        orientation: u8,
+    ) -> Result<(), RecognizeError> {
+        sqlx::query("UPDATE ProcessedFiles SET Orientation = $1 WHERE Id = $2")
+            .bind(orientation)
+            .bind(file_id)
             .execute(&self.db)
-            .await
-            .unwrap();
+            .await?;
 
-        res.last_insert_rowid()
+        Ok(())
     }
 
-    pub async fn locate_similar(&self, face_id: i64) -> Vec<i64> {
-        let res: Vec<(i64, f32)> = sqlx::query_as(
+    /// Finds up to `k` faces closest to the query, no further than `max_distance` and of at
+    /// least `min_quality`.
+    ///
+    /// Results are sorted by ascending distance. When querying by face id, the face itself
+    /// is excluded; an unknown face id yields no results. Faces stored before the quality was
+    /// computed are never left out. At most [`MAX_KNN_K`] faces are compared, one of them being
+    /// the query face itself when querying by face id.
+    pub async fn find_nearest(
+        &self,
+        query: NearestQuery<'_>,
+        k: u32,
+        max_distance: f32,
+        min_quality: Option<f64>,
+    ) -> Result<Vec<NearestFace>, RecognizeError> {
+        let (encoding, exclude_id): (Vec<u8>, Option<i64>) = match query {
+            NearestQuery::Face(face_id) => {
+                let encoding: Option<Vec<u8>> =
+                    sqlx::query_scalar("SELECT FaceEncoding FROM Faces WHERE Id = $1")
+                        .bind(face_id)
+                        .fetch_optional(&self.db)
+                        .await?;
+
+                match encoding {
+                    Some(encoding) => (encoding, Some(face_id)),
+                    None => return Ok(Vec::new()),
+                }
+            }
+            NearestQuery::Encoding(encoding) => {
+                (encoding_as_f32(encoding).as_bytes().to_vec(), None)
+            }
+        };
+
+        // the query face is its own nearest neighbour, so ask for one more
+        let knn_k = (k + exclude_id.is_some() as u32).min(MAX_KNN_K);
+
+        let rows: Vec<NearestFaceRow> = sqlx::query_as(
             "
             -- noinspection SqlResolve
+            WITH knn AS (
+              SELECT FaceId, distance
+              FROM FaceEncodings
+              WHERE FaceEncoding MATCH $1
+                AND k = $2
+                AND Quality >= $6
+            )
             SELECT
-              f.id,
-              vec_distance_L2(f.FaceEncoding, q.vec) AS distance
-            FROM Faces AS f
-            CROSS JOIN (SELECT FaceEncoding AS vec FROM Faces WHERE id = $1) AS q
-            WHERE distance > 0.6
-            ORDER BY distance DESC
-            LIMIT 30;
+              knn.FaceId,
+              knn.distance,
+              pf.Path,
+              f.RectLeft,
+              f.RectTop,
+              f.RectRight,
+              f.RectBottom
+            FROM knn
+            JOIN Faces AS f ON f.Id = knn.FaceId
+            LEFT JOIN ProcessedFiles AS pf ON pf.Id = f.FileId
+            WHERE ($3 IS NULL OR knn.FaceId != $3)
+              AND knn.distance <= $4
+            ORDER BY knn.distance
+            LIMIT $5;
             ",
         )
-        .bind(face_id)
+        .bind(encoding)
+        .bind(knn_k)
+        .bind(exclude_id)
+        .bind(max_distance)
+        .bind(k)
+        .bind(min_quality.unwrap_or(0.0))
         .fetch_all(&self.db)
-        .await
-        .unwrap();
+        .await?;
+
+        Ok(rows.into_iter().map(nearest_face_from_row).collect())
+    }
+
+    /// Stores all faces found in one file in a single transaction.
+    ///
+    /// Returns ids of the new faces in the order of `locations`.
+    pub(crate) async fn add_faces(
+        &self,
+        file_id: Option<i64>,
+        faces: &[FaceInsert<'_>],
+    ) -> Result<Vec<i64>, RecognizeError> {
+        let mut tx = self.db.begin().await?;
+        let mut face_ids = Vec::with_capacity(faces.len());
+
+        for face in faces {
+            let floats_f32 = encoding_as_f32(face.encoding);
+            let location = &face.location;
+
+            let res = sqlx::query(
+                "INSERT INTO Faces
+                         (FileId, FaceEncoding, RectLeft, RectTop, RectRight, RectBottom,
+                          Detector, Confidence, Rotation, Landmarks, Yaw, Pitch, Roll, Quality,
+                          Jitters)
+                     VALUES
+                         ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+                    ",
+            )
+            .bind(file_id)
+            .bind(floats_f32.as_bytes())
+            .bind(location.left as i64)
+            .bind(location.top as i64)
+            .bind(location.right as i64)
+            .bind(location.bottom as i64)
+            .bind(face.detector)
+            .bind(face.confidence)
+            .bind(face.rotation)
+            .bind(landmarks_as_bytes(face.landmarks))
+            .bind(face.pose.map(|pose| pose.yaw))
+            .bind(face.pose.map(|pose| pose.pitch))
+            .bind(face.pose.map(|pose| pose.roll))
+            .bind(face.quality)
+            .bind(face.jitters)
+            .execute(&mut *tx)
+            .await?;
+
+            let face_id = res.last_insert_rowid();
+
+            sqlx::query(
+                "
+                -- noinspection SqlResolve
+                INSERT INTO FaceEncodings (FaceId, FaceEncoding, PersonId, Quality)
+                VALUES ($1, $2, 0, $3)
+                ",
+            )
+            .bind(face_id)
+            .bind(floats_f32.as_bytes())
+            .bind(face.quality)
+            .execute(&mut *tx)
+            .await?;
 
-        for (id, dist) in res.iter() {
-            println!("{id}: distance: {dist}")
+            face_ids.push(face_id);
         }
 
-        res.into_iter().map(|(id, _)| id).collect()
+        tx.commit().await?;
+
+        Ok(face_ids)
+    }
+
+    /// Assigns the face to a person with the given name, creating the person if needed.
+    ///
+    /// Returns the person id, or `None` when there is no face with the given id.
+    pub async fn name_face(&self, face_id: i64, name: &str) -> Result<Option<i64>, RecognizeError> {
+        let mut tx = self.db.begin().await?;
+        let person_id = Self::upsert_person(&mut tx, name).await?;
+
+        let res =
+            sqlx::query("UPDATE Faces SET PersonId = $1, MatchedPersonId = NULL WHERE Id = $2")
+                .bind(person_id)
+                .bind(face_id)
+                .execute(&mut *tx)
+                .await?;
+
+        if res.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+
+        Self::sync_encoding_person(&mut tx, face_id, person_id).await?;
+        tx.commit().await?;
+
+        Ok(Some(person_id))
     }
 
-    pub(crate) async fn add_face(
+    /// Assigns all unlabeled faces of a cluster to a person with the given name.
+    ///
+    /// Faces matched automatically count as unlabeled, so naming the cluster confirms or
+    /// corrects their matches. Returns the person id and the number of named faces, or `None`
+    /// when the cluster has no unlabeled faces.
+    pub async fn name_cluster(
         &self,
-        file_id: Option<i64>,
-        encoding: &FaceEncoding,
-        location: &Rectangle,
-    ) -> i64 {
-        let floats_f32: Vec<f32> = encoding.to_vec().iter().map(|&d| d as f32).collect();
-
-        let res = sqlx::query(
-            "INSERT INTO Faces
-                     (FileId, FaceEncoding, RectLeft, RectTop, RectRight, RectBottom)
-                 VALUES
-                     ($1, $2, $3, $4, $5, $6)
+        cluster_id: i64,
+        name: &str,
+    ) -> Result<Option<(i64, usize)>, RecognizeError> {
+        let mut tx = self.db.begin().await?;
+
+        let face_ids: Vec<i64> =
+            sqlx::query_scalar("SELECT Id FROM Faces WHERE ClusterId = $1 AND PersonId IS NULL")
+                .bind(cluster_id)
+                .fetch_all(&mut *tx)
+                .await?;
+
+        if face_ids.is_empty() {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+
+        let person_id = Self::upsert_person(&mut tx, name).await?;
+
+        for face_id in face_ids.iter() {
+            sqlx::query("UPDATE Faces SET PersonId = $1, MatchedPersonId = NULL WHERE Id = $2")
+                .bind(person_id)
+                .bind(*face_id)
+                .execute(&mut *tx)
+                .await?;
+
+            Self::sync_encoding_person(&mut tx, *face_id, person_id).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some((person_id, face_ids.len())))
+    }
+
+    async fn upsert_person(tx: &mut Transaction<'_, Sqlite>, name: &str) -> sqlx::Result<i64> {
+        sqlx::query("INSERT INTO Persons (Name) VALUES ($1) ON CONFLICT (Name) DO NOTHING")
+            .bind(name)
+            .execute(&mut **tx)
+            .await?;
+
+        sqlx::query_scalar("SELECT Id FROM Persons WHERE Name = $1")
+            .bind(name)
+            .fetch_one(&mut **tx)
+            .await
+    }
+
+    /// Builds a similarity graph of all unlabeled faces.
+    ///
+    /// Returns the face ids and edges between faces given as indices into that list. Each
+    /// face is connected to at most `k` of its nearest unlabeled faces within `max_distance`.
+    /// Faces with a quality below `min_quality` are left out; those stored before the quality
+    /// was computed are kept.
+    pub async fn unlabeled_face_graph(
+        &self,
+        k: u32,
+        max_distance: f32,
+        min_quality: Option<f64>,
+    ) -> Result<(Vec<i64>, Vec<(usize, usize)>), RecognizeError> {
+        let faces: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+            "SELECT Id, FaceEncoding FROM Faces
+             WHERE PersonId IS NULL AND ($1 IS NULL OR Quality IS NULL OR Quality >= $1)
+             ORDER BY Id",
+        )
+        .bind(min_quality)
+        .fetch_all(&self.db)
+        .await?;
+
+        let face_ids: Vec<i64> = faces.iter().map(|(id, _)| *id).collect();
+        let mut edges = Vec::new();
+
+        for (index, (face_id, encoding)) in faces.iter().enumerate() {
+            let neighbours: Vec<i64> = sqlx::query_scalar(
+                "
+                -- noinspection SqlResolve
+                WITH knn AS (
+                  SELECT FaceId, distance
+                  FROM FaceEncodings
+                  WHERE FaceEncoding MATCH $1
+                    AND k = $2
+                    AND PersonId = 0
+                    AND Quality >= $5
+                )
+                SELECT FaceId FROM knn WHERE FaceId != $3 AND distance <= $4;
                 ",
+            )
+            .bind(encoding)
+            .bind(k + 1)
+            .bind(face_id)
+            .bind(max_distance)
+            .bind(min_quality.unwrap_or(0.0))
+            .fetch_all(&self.db)
+            .await?;
+
+            for neighbour_id in neighbours {
+                // both lists are sorted by id
+                if let Ok(neighbour_index) = face_ids.binary_search(&neighbour_id) {
+                    edges.push((index, neighbour_index));
+                }
+            }
+        }
+
+        Ok((face_ids, edges))
+    }
+
+    /// Replaces all stored cluster assignments; cluster ids are numbered from 1.
+    pub async fn set_clusters(&self, clusters: &[Vec<i64>]) -> Result<(), RecognizeError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("UPDATE Faces SET ClusterId = NULL WHERE ClusterId IS NOT NULL")
+            .execute(&mut *tx)
+            .await?;
+
+        for (index, face_ids) in clusters.iter().enumerate() {
+            for face_id in face_ids {
+                sqlx::query("UPDATE Faces SET ClusterId = $1 WHERE Id = $2")
+                    .bind(index as i64 + 1)
+                    .bind(*face_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Finds up to `k` labeled faces closest to the given encoding.
+    ///
+    /// Only faces named by the user are considered, not those matched automatically.
+    /// Returns `(person_id, distance)` pairs sorted by ascending distance.
+    pub async fn find_nearest_labeled(
+        &self,
+        encoding: &FaceEncoding,
+        k: u32,
+    ) -> Result<Vec<(i64, f32)>, RecognizeError> {
+        let floats_f32 = encoding_as_f32(encoding);
+
+        let neighbours = sqlx::query_as(
+            "
+            -- noinspection SqlResolve
+            SELECT PersonId, distance
+            FROM FaceEncodings
+            WHERE FaceEncoding MATCH $1
+              AND k = $2
+              AND PersonId > 0
+            ORDER BY distance;
+            ",
         )
-        .bind(file_id)
         .bind(floats_f32.as_bytes())
-        .bind(location.left as i64)
-        .bind(location.top as i64)
-        .bind(location.right as i64)
-        .bind(location.bottom as i64)
-        .execute(&self.db)
-        .await
-        .unwrap();
+        .bind(k)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(neighbours)
+    }
+
+    /// Records a person the face was matched to automatically.
+    ///
+    /// Unlike [`Self::name_face`] this leaves the face unlabeled, so a wrong match does not
+    /// take part in later matches and the face can still be named through its cluster.
+    pub async fn assign_person(&self, face_id: i64, person_id: i64) -> Result<(), RecognizeError> {
+        sqlx::query("UPDATE Faces SET MatchedPersonId = $1 WHERE Id = $2")
+            .bind(person_id)
+            .bind(face_id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mirrors a `Faces.PersonId` change into the `FaceEncodings` vec0 table.
+    async fn sync_encoding_person(
+        tx: &mut Transaction<'_, Sqlite>,
+        face_id: i64,
+        person_id: i64,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "
+            -- noinspection SqlResolve
+            UPDATE FaceEncodings SET PersonId = $1 WHERE FaceId = $2
+            ",
+        )
+        .bind(person_id)
+        .bind(face_id)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Name of the person the face was labeled with or, failing that, matched to.
+    pub async fn find_person_name(&self, face_id: i64) -> Result<Option<String>, RecognizeError> {
+        let name = sqlx::query_scalar(
+            "SELECT p.Name
+             FROM Faces AS f
+             JOIN Persons AS p ON p.Id = coalesce(f.PersonId, f.MatchedPersonId)
+             WHERE f.Id = $1",
+        )
+        .bind(face_id)
+        .fetch_optional(&self.db)
+        .await?;
 
-        res.last_insert_rowid()
+        Ok(name)
+    }
+
+    /// Faces of the person with the given name, optionally only those turned no more than
+    /// `max_angle` degrees in any direction, i.e. roughly frontal ones.
+    ///
+    /// Faces matched to the person automatically are included. The most frontal faces come
+    /// first and those without an estimated pose last, or not at all when `max_angle` is given.
+    pub async fn find_person_faces(
+        &self,
+        name: &str,
+        max_angle: Option<f64>,
+    ) -> Result<Vec<PersonFace>, RecognizeError> {
+        let rows: Vec<PersonFaceRow> = sqlx::query_as(
+            "SELECT f.Id, pf.Path, f.RectLeft, f.RectTop, f.RectRight, f.RectBottom,
+                    f.Yaw, f.Pitch, f.Roll
+             FROM Faces AS f
+             JOIN Persons AS p ON p.Id = coalesce(f.PersonId, f.MatchedPersonId)
+             LEFT JOIN ProcessedFiles AS pf ON pf.Id = f.FileId
+             WHERE p.Name = $1
+               AND ($2 IS NULL OR (abs(f.Yaw) <= $2 AND abs(f.Pitch) <= $2))
+             ORDER BY f.Yaw IS NULL, abs(f.Yaw) + abs(f.Pitch)",
+        )
+        .bind(name)
+        .bind(max_angle)
+        .fetch_all(&self.db)
+        .await?;
+
+        let faces = rows.into_iter().map(person_face_from_row).collect();
+
+        Ok(faces)
+    }
+
+    /// The face with the given id, whether or not it belongs to a person.
+    pub async fn find_face(&self, face_id: i64) -> Result<Option<PersonFace>, RecognizeError> {
+        let row: Option<PersonFaceRow> = sqlx::query_as(
+            "SELECT f.Id, pf.Path, f.RectLeft, f.RectTop, f.RectRight, f.RectBottom,
+                    f.Yaw, f.Pitch, f.Roll
+             FROM Faces AS f
+             LEFT JOIN ProcessedFiles AS pf ON pf.Id = f.FileId
+             WHERE f.Id = $1",
+        )
+        .bind(face_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(person_face_from_row))
+    }
+
+    /// Landmarks of the face in image coordinates, `None` if the face has none stored.
+    pub async fn find_landmarks(&self, face_id: i64) -> Result<Option<Vec<Point>>, RecognizeError> {
+        let landmarks: Option<Option<Vec<u8>>> =
+            sqlx::query_scalar("SELECT Landmarks FROM Faces WHERE Id = $1")
+                .bind(face_id)
+                .fetch_optional(&self.db)
+                .await?;
+
+        Ok(landmarks
+            .flatten()
+            .map(|bytes| landmarks_from_bytes(&bytes)))
     }
 }
 
+/// Face id, distance, file path and rectangle columns.
+type NearestFaceRow = (i64, f32, Option<String>, i64, i64, i64, i64);
+
+fn nearest_face_from_row(
+    (face_id, distance, path, left, top, right, bottom): NearestFaceRow,
+) -> NearestFace {
+    NearestFace {
+        face_id,
+        distance,
+        path,
+        rect: Rectangle {
+            left: left as u64,
+            top: top as u64,
+            right: right as u64,
+            bottom: bottom as u64,
+        },
+    }
+}
+
+/// Face id, file path, rectangle and pose columns.
+type PersonFaceRow = (
+    i64,
+    Option<String>,
+    i64,
+    i64,
+    i64,
+    i64,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+);
+
+fn person_face_from_row(
+    (face_id, path, left, top, right, bottom, yaw, pitch, roll): PersonFaceRow,
+) -> PersonFace {
+    PersonFace {
+        face_id,
+        path,
+        rect: Rectangle {
+            left: left as u64,
+            top: top as u64,
+            right: right as u64,
+            bottom: bottom as u64,
+        },
+        pose: match (yaw, pitch, roll) {
+            (Some(yaw), Some(pitch), Some(roll)) => Some(HeadPose { yaw, pitch, roll }),
+            _ => None,
+        },
+    }
+}
+
+fn encoding_as_f32(encoding: &FaceEncoding) -> Vec<f32> {
+    encoding.iter().map(|&d| d as f32).collect()
+}
+
+/// Little endian, so that the database can be moved between machines.
+fn landmarks_as_bytes(landmarks: &[Point]) -> Vec<u8> {
+    landmarks
+        .iter()
+        .flat_map(|point| [point.x as i32, point.y as i32])
+        .flat_map(i32::to_le_bytes)
+        .collect()
+}
+
+fn landmarks_from_bytes(bytes: &[u8]) -> Vec<Point> {
+    bytes
+        .chunks_exact(8)
+        .map(|chunk| Point {
+            x: i32::from_le_bytes(chunk[0..4].try_into().unwrap()) as i64,
+            y: i32::from_le_bytes(chunk[4..8].try_into().unwrap()) as i64,
+        })
+        .collect()
+}
+
 impl PersonRegistrySqlite {
-    pub async fn initialize() -> Self {
-        let db = PersonRegistrySqlite::setup_db().await;
+    pub async fn initialize() -> Result<Self, RecognizeError> {
+        let db = PersonRegistrySqlite::setup_db().await?;
 
-        Self { db }
+        Ok(Self { db })
     }
 
-    async fn setup_db() -> Db {
+    async fn setup_db() -> Result<Db, RecognizeError> {
         let mut path = PROJECT_DIRS.data_dir().to_path_buf();
 
-        match fs::create_dir_all(path.clone()) {
-            Ok(_) => {}
-            Err(err) => {
-                panic!("error creating directory {}", err);
-            }
-        };
+        fs::create_dir_all(&path).map_err(|err| RecognizeError::io(&path, err))?;
 
         path.push("db.sqlite");
 
-        let result = OpenOptions::new().create(true).write(true).open(&path);
-
-        match result {
-            Ok(_) => {}
-            Err(err) => panic!("error creating database file {}", err),
-        }
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|err| RecognizeError::io(&path, err))?;
 
         unsafe {
-            libsqlite3_sys::sqlite3_auto_extension(Some(std::mem::transmute(
-                sqlite3_vec_init as *const (),
+            libsqlite3_sys::sqlite3_auto_extension(Some(std::mem::transmute::<
+                *const (),
+                unsafe extern "C" fn(
+                    *mut libsqlite3_sys::sqlite3,
+                    *mut *mut c_char,
+                    *const libsqlite3_sys::sqlite3_api_routines,
+                ) -> c_int,
+            >(
+                sqlite3_vec_init as *const ()
             )));
         }
 
-        let db = SqlitePoolOptions::new()
-            .connect(path.to_str().unwrap())
-            .await
-            .unwrap();
+        // sqlite is given the path as a string
+        let url = path.to_str().ok_or_else(|| {
+            RecognizeError::io(
+                &path,
+                io::Error::new(io::ErrorKind::InvalidData, "path is not valid UTF-8"),
+            )
+        })?;
+        let db = SqlitePoolOptions::new().connect(url).await?;
 
         info!("Executing migrations...");
-        sqlx::migrate!("./src/migrations").run(&db).await.unwrap();
+        sqlx::migrate!("./src/migrations").run(&db).await?;
 
         let version: (String,) = sqlx::query_as("SELECT sqlite_version();")
             .fetch_one(&db)
-            .await
-            .unwrap();
+            .await?;
 
         let vec_version: (String,) = sqlx::query_as(
             "
@@ -164,8 +695,7 @@ impl PersonRegistrySqlite {
             ",
         )
         .fetch_one(&db)
-        .await
-        .unwrap();
+        .await?;
 
         println!("sqlite version: {:?}", version);
         println!("vec version: {:?}", vec_version);
@@ -177,9 +707,8 @@ impl PersonRegistrySqlite {
         ",
         )
         .execute(&db)
-        .await
-        .unwrap();
+        .await?;
 
-        db
+        Ok(db)
     }
 }
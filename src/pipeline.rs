@@ -0,0 +1,291 @@
+//! Staged pipeline recognizing faces in many files concurrently.
+//!
+//! Files flow through three stages connected by bounded channels:
+//! - hashing, the processed check and decoding run on the blocking thread pool,
+//! - detection and encoding run on dedicated worker threads, each owning a copy of the models;
+//!   faces of the few files waiting at the same time are encoded together in one batch,
+//! - the results are written to the registry by a single task, one transaction per file,
+//!   while annotated copies of the images are drawn on the blocking thread pool.
+
+use crate::error::RecognizeError;
+use crate::face_recognizer::{
+    DecodedImage, DetectResult, DetectedFace, EncodingOptions, FaceRecognizer,
+    FaceRecognizerOptions, LocatedFace,
+};
+use blake3::Hash;
+use dlib_wrappers::ImageMatrix;
+use flume::TryRecvError;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::{Mutex, Semaphore, mpsc};
+use tokio::task;
+
+/// Files with located faces a detection worker keeps waiting for more faces to encode at most.
+///
+/// Each of them holds the decoded image and its copy converted for dlib, so a full batch is
+/// not worth keeping many of them in memory.
+const MAX_PENDING_FILES: usize = 4;
+
+/// A file decoded and waiting for a detection worker.
+struct DecodedFile {
+    path: PathBuf,
+    hash: Hash,
+    processed_file_id: Option<i64>,
+    image: DecodedImage,
+}
+
+impl DecodedFile {
+    fn detected(self, faces: Vec<DetectedFace>) -> DetectedFile {
+        DetectedFile {
+            path: self.path,
+            hash: self.hash,
+            processed_file_id: self.processed_file_id,
+            image: self.image,
+            faces,
+        }
+    }
+}
+
+/// A file analysed by a detection worker and waiting to be stored.
+struct DetectedFile {
+    path: PathBuf,
+    hash: Hash,
+    processed_file_id: Option<i64>,
+    image: DecodedImage,
+    faces: Vec<DetectedFace>,
+}
+
+/// Starts recognizing faces in the files using `jobs` workers per stage.
+///
+/// Results are delivered in the order in which files finish, not in the order of `paths`.
+/// A failure of one file does not stop the others.
+pub(crate) fn recognize_files(
+    paths: Vec<PathBuf>,
+    recognizer: Arc<FaceRecognizer>,
+    options: FaceRecognizerOptions,
+    jobs: usize,
+) -> mpsc::Receiver<(PathBuf, Result<DetectResult, RecognizeError>)> {
+    let jobs = jobs.max(1);
+    let options = Arc::new(options);
+
+    let (paths_tx, paths_rx) = mpsc::channel(jobs * 2);
+    // detection workers share one receiver, so that a worker waiting for a file does not keep
+    // the others from checking whether there is one
+    let (decoded_tx, decoded_rx) = flume::bounded::<DecodedFile>(jobs * 2);
+    let (detected_tx, mut detected_rx) = mpsc::channel::<DetectedFile>(jobs * 2);
+    let (results_tx, results_rx) = mpsc::channel(jobs * 2);
+
+    tokio::spawn(async move {
+        for path in paths {
+            if paths_tx.send(path).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let paths_rx = Arc::new(Mutex::new(paths_rx));
+    for _ in 0..jobs {
+        let paths_rx = paths_rx.clone();
+        let decoded_tx = decoded_tx.clone();
+        let results_tx = results_tx.clone();
+        let recognizer = recognizer.clone();
+        let options = options.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let path = paths_rx.lock().await.recv().await;
+                let Some(path) = path else { break };
+
+                let (path, hash) = task::spawn_blocking(move || {
+                    let hash = FaceRecognizer::calc_hash(&path);
+                    (path, hash)
+                })
+                .await
+                .unwrap();
+
+                let decoded = match hash {
+                    Ok(hash) => decode(&recognizer, path.clone(), hash, &options).await,
+                    Err(err) => Err(err),
+                };
+
+                let sent = match decoded {
+                    Ok(Some(file)) => decoded_tx.send_async(file).await.is_ok(),
+                    Ok(None) => results_tx
+                        .send((path, Ok(DetectResult::Skipped)))
+                        .await
+                        .is_ok(),
+                    Err(err) => results_tx.send((path, Err(err))).await.is_ok(),
+                };
+
+                if !sent {
+                    break;
+                }
+            }
+        });
+    }
+    drop(decoded_tx);
+
+    // dlib networks are not safe to share between threads, so each worker gets its own copy
+    for _ in 0..jobs {
+        let worker = recognizer.with_own_models();
+        let decoded_rx = decoded_rx.clone();
+        let detected_tx = detected_tx.clone();
+        let options = options.clone();
+
+        thread::spawn(move || {
+            // files with located faces, waiting to be encoded together
+            let mut pending: Vec<(DecodedFile, ImageMatrix, Vec<LocatedFace>)> = Vec::new();
+
+            loop {
+                // wait for files only when there is nothing else to do
+                let next = if pending.is_empty() {
+                    decoded_rx.recv().map_err(|_| TryRecvError::Disconnected)
+                } else {
+                    decoded_rx.try_recv()
+                };
+
+                match next {
+                    Ok(file) => {
+                        let matrix = ImageMatrix::from_image(&file.image.rgb);
+                        let faces =
+                            worker.locate_faces(&file.image.rgb, &matrix, &options.detection);
+
+                        // there is nothing to encode in files without faces
+                        if faces.is_empty() {
+                            if detected_tx
+                                .blocking_send(file.detected(Vec::new()))
+                                .is_err()
+                            {
+                                return;
+                            }
+                            continue;
+                        }
+                        pending.push((file, matrix, faces));
+
+                        let pending_faces: usize =
+                            pending.iter().map(|(_, _, faces)| faces.len()).sum();
+                        if pending_faces < options.encoding.batch_size
+                            && pending.len() < MAX_PENDING_FILES
+                        {
+                            continue;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => {}
+                    Err(TryRecvError::Disconnected) if pending.is_empty() => break,
+                    Err(TryRecvError::Disconnected) => {}
+                }
+
+                for file in encode_files(&worker, mem::take(&mut pending), &options.encoding) {
+                    if detected_tx.blocking_send(file).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+    drop(detected_tx);
+
+    // annotated images are drawn and saved next to storing the following files, but only a
+    // few at a time as each holds a decoded image
+    let annotating = Arc::new(Semaphore::new(jobs));
+    tokio::spawn(async move {
+        while let Some(file) = detected_rx.recv().await {
+            let stored_faces = recognizer
+                .store_faces(
+                    file.hash,
+                    &file.path,
+                    file.processed_file_id,
+                    file.image.orientation,
+                    &file.faces,
+                    &options,
+                )
+                .await;
+
+            let (stored_faces, target) = match (stored_faces, &options.annotate) {
+                (Ok(Some(stored_faces)), Some(target)) => (stored_faces, target.clone()),
+                (stored_faces, _) => {
+                    let result = stored_faces.map(|faces| match faces {
+                        Some(faces) => DetectResult::from_stored_faces(&faces),
+                        None => DetectResult::Skipped,
+                    });
+                    if results_tx.send((file.path, result)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            let permit = annotating.clone().acquire_owned().await.unwrap();
+            let recognizer = recognizer.clone();
+            let results_tx = results_tx.clone();
+            tokio::spawn(async move {
+                let result = recognizer
+                    .write_annotated(
+                        &file.path,
+                        file.image.rgb,
+                        &file.faces,
+                        &stored_faces,
+                        &target,
+                    )
+                    .await
+                    .map(|()| DetectResult::from_stored_faces(&stored_faces));
+                drop(permit);
+
+                let _ = results_tx.send((file.path, result)).await;
+            });
+        }
+    });
+
+    results_rx
+}
+
+/// Encodes faces of all the files in one batch.
+fn encode_files(
+    worker: &FaceRecognizer,
+    files: Vec<(DecodedFile, ImageMatrix, Vec<LocatedFace>)>,
+    options: &EncodingOptions,
+) -> Vec<DetectedFile> {
+    let (files, images): (Vec<DecodedFile>, Vec<(ImageMatrix, Vec<LocatedFace>)>) = files
+        .into_iter()
+        .map(|(file, matrix, faces)| (file, (matrix, faces)))
+        .unzip();
+    let (matrices, faces): (Vec<ImageMatrix>, Vec<Vec<LocatedFace>>) = images.into_iter().unzip();
+
+    let images = matrices.iter().zip(faces).collect();
+    let faces = worker.encode_faces(images, options);
+
+    files
+        .into_iter()
+        .zip(faces)
+        .map(|(file, faces)| file.detected(faces))
+        .collect()
+}
+
+/// Decodes the file, or returns `None` when it has already been processed.
+async fn decode(
+    recognizer: &FaceRecognizer,
+    path: PathBuf,
+    hash: Hash,
+    options: &FaceRecognizerOptions,
+) -> Result<Option<DecodedFile>, RecognizeError> {
+    let processed_file_id = recognizer.find_processed_file(&hash).await?;
+    if processed_file_id.is_some() && !options.skip_processed_check {
+        return Ok(None);
+    }
+
+    let (path, image) = task::spawn_blocking(move || {
+        let image = FaceRecognizer::open_image(&path);
+        (path, image)
+    })
+    .await
+    .unwrap();
+
+    Ok(Some(DecodedFile {
+        path,
+        hash,
+        processed_file_id,
+        image: image?,
+    }))
+}